@@ -16,9 +16,13 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     config.cgroup_limits.set_memory_limit(512 * 1024 * 1024); // 512Mb
     config.time_limit = std::time::Duration::from_secs(1);
     config.stdout = Some("/root/sandbox/io/output.txt".to_string());
+    config.stdout_limit = Some(64 * 1024 * 1024); // 64Mb
     let mut c = Container::from(config);
     c.start()?;
-    c.wait()?;
+    let report = c.wait()?;
+    if report.output_limit_exceeded {
+        println!("Output Limit Exceeded");
+    }
     println!("Finished!");
     Ok(())
 }
@@ -1,5 +1,5 @@
 // You need to have a alpine image @ /root/sandbox/image to run this example
-// You will get a shell inside the container.
+// You will get a shell inside the container, on a pseudo-terminal of its own.
 
 #![feature(box_syntax)]
 #![feature(type_ascription)]
@@ -16,7 +16,11 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
     config.cgroup_limits.set_memory_limit(512 * 1024 * 1024); // 512Mb
     config.time_limit = std::time::Duration::from_secs(200);
     let mut c = Container::from(config);
+    c.set_pty(true);
     c.start()?;
+    if let Some(pty) = c.pty() {
+        pty.proxy_stdio()?;
+    }
     c.wait()?;
     println!("Finished!");
     Ok(())
@@ -0,0 +1,293 @@
+use {
+    super::{error, process::Process, Container, ExitReport, Input, Output},
+    crate::{CommonResult, VoidResult},
+    nix::{fcntl::OFlag, sys::signal, unistd},
+    std::{
+        os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        sync::Arc,
+    },
+    tokio::{
+        io::{unix::AsyncFd, AsyncReadExt, AsyncWriteExt},
+        net::unix::pipe,
+        signal::unix::{signal as unix_signal, Signal, SignalKind},
+        task::JoinHandle,
+        time::Instant,
+    },
+};
+
+fn make_pipe() -> CommonResult<(OwnedFd, OwnedFd)> {
+    let (read, write) = unistd::pipe2(OFlag::O_CLOEXEC)?;
+    Ok(unsafe { (OwnedFd::from_raw_fd(read), OwnedFd::from_raw_fd(write)) })
+}
+
+// The fd must stay open until the returned `AsyncFd` is dropped.
+fn register<T: AsRawFd>(fd: T) -> CommonResult<AsyncFd<T>> {
+    let res = unsafe { AsyncFd::register(fd) };
+    Ok(res.map_err(std::io::Error::from)?)
+}
+
+// Borrowed file descriptor, only to be registered to the reactor.
+struct Fd(RawFd);
+
+impl AsRawFd for Fd {
+    fn as_raw_fd(&self) -> RawFd {
+        self.0
+    }
+}
+
+// How the exit of the container gets noticed: a pidfd becomes readable once the process exits,
+// and kernels without pidfd support fall back to watching SIGCHLD.
+enum ExitNotifier {
+    PidFd(AsyncFd<OwnedFd>),
+    ChildSignal(Signal),
+}
+
+impl ExitNotifier {
+    fn new(process: &Process) -> CommonResult<Self> {
+        match process.pidfd() {
+            Some(fd) => {
+                let fd = unsafe { OwnedFd::from_raw_fd(unistd::dup(fd)?) };
+                Ok(ExitNotifier::PidFd(register(fd)?))
+            }
+            None => Ok(ExitNotifier::ChildSignal(unix_signal(SignalKind::child())?)),
+        }
+    }
+
+    async fn notified(&mut self) -> VoidResult {
+        match self {
+            ExitNotifier::PidFd(fd) => {
+                let mut guard = fd.readable().await?;
+                guard.clear_ready();
+            }
+            ExitNotifier::ChildSignal(signal) => {
+                signal.recv().await;
+            }
+        }
+        Ok(())
+    }
+}
+
+async fn feed_input(data: Vec<u8>, mut pipe: pipe::Sender) {
+    // the container may quit without reading all the input, so write errors are ignored
+    let _ = pipe.write_all(&data).await;
+}
+
+// Returns the captured data, and whether there was more than `limit` bytes, in which case the
+// container is killed.
+async fn capture_output(
+    limit: usize,
+    mut pipe: pipe::Receiver,
+    process: Arc<Process>,
+) -> (Vec<u8>, bool) {
+    let mut res = Vec::new();
+    let mut buf = [0_u8; 8192];
+    loop {
+        let len = match pipe.read(&mut buf).await {
+            Ok(0) | Err(_) => return (res, false),
+            Ok(x) => x,
+        };
+
+        if res.len() + len > limit {
+            let _ = process.signal(signal::SIGKILL);
+            let allowed = limit - res.len();
+            res.extend_from_slice(&buf[..allowed]);
+            return (res, true);
+        }
+        res.extend_from_slice(&buf[..len]);
+    }
+}
+
+// Errors are not `Send` in general, those of containers are passed between threads as they are,
+// and the others by their messages.
+fn into_sendable(err: Box<dyn std::error::Error>) -> Box<dyn std::error::Error + Send + Sync> {
+    match err.downcast::<error::Error>() {
+        Ok(x) => x,
+        Err(x) => x.to_string().into(),
+    }
+}
+
+// The container is taken out while being prepared on another thread, and lost if `start()` is
+// cancelled meanwhile.
+fn container(inner: &mut Option<Container>) -> CommonResult<&mut Container> {
+    match inner {
+        Some(inner) => Ok(inner),
+        None => Err(Box::new(error::Error::NotStarted)),
+    }
+}
+
+// Asynchronous wrapper of `Container`, for supervising many containers on a few threads. Streams
+// are pumped on the runtime, and the time limit is enforced with a timer instead of a watchdog.
+pub struct AsyncContainer {
+    inner: Option<Container>,
+    stdin: Option<Vec<u8>>,
+    stdout_limit: Option<usize>,
+    stderr_limit: Option<usize>,
+    notifier: Option<ExitNotifier>,
+    deadline: Option<Instant>,
+    stdin_task: Option<JoinHandle<()>>,
+    stdout_task: Option<JoinHandle<(Vec<u8>, bool)>>,
+    stderr_task: Option<JoinHandle<(Vec<u8>, bool)>>,
+}
+
+impl std::fmt::Debug for AsyncContainer {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("AsyncContainer")
+            .field("inner", &self.inner)
+            .finish()
+    }
+}
+
+impl From<Container> for AsyncContainer {
+    fn from(source: Container) -> Self {
+        Self {
+            inner: Some(source),
+            stdin: None,
+            stdout_limit: None,
+            stderr_limit: None,
+            notifier: None,
+            deadline: None,
+            stdin_task: None,
+            stdout_task: None,
+            stderr_task: None,
+        }
+    }
+}
+
+impl AsyncContainer {
+    // None once `start()` has been cancelled while preparing.
+    pub fn inner(&self) -> Option<&Container> {
+        self.inner.as_ref()
+    }
+
+    pub fn inner_mut(&mut self) -> Option<&mut Container> {
+        self.inner.as_mut()
+    }
+
+    pub fn set_stdin(&mut self, data: Vec<u8>) -> &mut Self {
+        self.stdin = Some(data);
+        self
+    }
+
+    // Captures the standard output into memory, up to `limit` bytes.
+    pub fn capture_stdout(&mut self, limit: usize) -> &mut Self {
+        self.stdout_limit = Some(limit);
+        self
+    }
+
+    pub fn capture_stderr(&mut self, limit: usize) -> &mut Self {
+        self.stderr_limit = Some(limit);
+        self
+    }
+
+    // Returns the read ends of the captured outputs, to be pumped once the container exists.
+    fn setup_stdio(&mut self) -> CommonResult<[Option<(usize, pipe::Receiver)>; 2]> {
+        if let Some(data) = self.stdin.take() {
+            let (read, write) = make_pipe()?;
+            container(&mut self.inner)?.set_stdin(Input::Fd(read));
+            let write = pipe::Sender::from_owned_fd(write)?;
+            self.stdin_task = Some(tokio::spawn(feed_input(data, write)));
+        }
+
+        let mut res = [None, None];
+        if let Some(limit) = self.stdout_limit {
+            let (read, write) = make_pipe()?;
+            container(&mut self.inner)?.set_stdout(Output::Fd(write));
+            res[0] = Some((limit, pipe::Receiver::from_owned_fd(read)?));
+        }
+
+        if let Some(limit) = self.stderr_limit {
+            let (read, write) = make_pipe()?;
+            container(&mut self.inner)?.set_stderr(Output::Fd(write));
+            res[1] = Some((limit, pipe::Receiver::from_owned_fd(read)?));
+        }
+        Ok(res)
+    }
+
+    // Preparing blocks on the child for a while, so it is done on a thread of its own.
+    async fn prepare(&mut self) -> VoidResult {
+        let mut inner = match self.inner.take() {
+            Some(inner) => inner,
+            None => return Err(Box::new(error::Error::NotStarted)),
+        };
+        let (inner, res) = tokio::task::spawn_blocking(move || {
+            let res = inner.prepare().map_err(into_sendable);
+            (inner, res)
+        })
+        .await?;
+        self.inner = Some(inner);
+        res.map_err(|x| -> Box<dyn std::error::Error> { x })
+    }
+
+    pub async fn start(&mut self) -> VoidResult {
+        let [stdout, stderr] = self.setup_stdio()?;
+        self.prepare().await?;
+        let process = match container(&mut self.inner)?.process() {
+            Some(process) => process.clone(),
+            None => return Err(Box::new(error::Error::NotStarted)),
+        };
+        self.notifier = Some(ExitNotifier::new(&process)?);
+        if let Some((limit, read)) = stdout {
+            let task = capture_output(limit, read, process.clone());
+            self.stdout_task = Some(tokio::spawn(task));
+        }
+        if let Some((limit, read)) = stderr {
+            let task = capture_output(limit, read, process.clone());
+            self.stderr_task = Some(tokio::spawn(task));
+        }
+
+        let inner = container(&mut self.inner)?;
+        let report_pipe = inner.launch_begin()?;
+        {
+            let fd = register(Fd(report_pipe))?;
+            let mut guard = fd.readable().await?;
+            guard.clear_ready();
+        }
+        inner.launch_end(report_pipe, false)?;
+        self.deadline = Some(Instant::now() + inner.config.time_limit);
+        Ok(())
+    }
+
+    pub async fn wait(&mut self) -> CommonResult<ExitReport> {
+        let process = match container(&mut self.inner)?.process() {
+            Some(process) => process.clone(),
+            None => return Err(box error::Error::NotStarted),
+        };
+        let (mut notifier, deadline) = match (self.notifier.take(), self.deadline) {
+            (Some(notifier), Some(deadline)) => (notifier, deadline),
+            _ => return Err(box error::Error::NotStarted),
+        };
+
+        let deadline = tokio::time::sleep_until(deadline);
+        tokio::pin!(deadline);
+        let mut timed_out = false;
+        let mut report = loop {
+            if let Some(report) = container(&mut self.inner)?.try_wait()? {
+                break report;
+            }
+
+            tokio::select! {
+                res = notifier.notified() => res?,
+                _ = &mut deadline, if !timed_out => {
+                    timed_out = true;
+                    container(&mut self.inner)?.mark_time_limit_exceeded();
+                    process.signal(signal::SIGKILL)?;
+                }
+            }
+        };
+
+        if let Some(task) = self.stdin_task.take() {
+            let _ = task.await;
+        }
+        if let Some(task) = self.stdout_task.take() {
+            let (data, exceeded) = task.await?;
+            report.stdout = data;
+            report.output_limit_exceeded |= exceeded;
+        }
+        if let Some(task) = self.stderr_task.take() {
+            let (data, exceeded) = task.await?;
+            report.stderr = data;
+            report.output_limit_exceeded |= exceeded;
+        }
+        Ok(report)
+    }
+}
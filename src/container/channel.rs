@@ -0,0 +1,137 @@
+use {
+    super::error::{AtStage, SetupError, SetupStage},
+    crate::{CommonResult, VoidResult},
+    nix::{errno::Errno, unistd},
+    std::{convert::TryInto, os::unix::io::RawFd},
+};
+
+// Anything longer is not something we sent.
+const MAX_PAYLOAD_LENGTH: usize = 1024 * 1024;
+
+// Messages passed over the pipes between the parent and a container being set up.
+//
+// Setting up goes like this:
+// child `Ready` -> parent maps ids and applies cgroups, `Mapped` -> child sets itself up,
+// `SetupDone` or `Failed` -> parent `Launch` -> child executes the target, the report pipe gets
+// closed by `execve()` as it is CLOEXEC, or `Failed` is sent if executing fails.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Message {
+    Ready,
+    Mapped,
+    SetupDone,
+    Launch,
+    Failed(SetupError),
+    Exited(i32), // raw wait status of the target, sent by the built-in init
+}
+
+// Every message is framed as a tag byte, the length of its payload as little-endian u32, and the
+// payload itself.
+fn encode(message: &Message) -> Vec<u8> {
+    let (tag, payload) = match message {
+        Message::Ready => (0, Vec::new()),
+        Message::Mapped => (1, Vec::new()),
+        Message::SetupDone => (2, Vec::new()),
+        Message::Launch => (3, Vec::new()),
+        Message::Failed(err) => (4, err.encode()),
+        Message::Exited(status) => (5, status.to_le_bytes().to_vec()),
+    };
+    let mut res = vec![tag];
+    res.extend_from_slice(&(payload.len() as u32).to_le_bytes());
+    res.extend_from_slice(&payload);
+    res
+}
+
+fn decode(tag: u8, payload: &[u8]) -> Option<Message> {
+    match (tag, payload.len()) {
+        (0, 0) => Some(Message::Ready),
+        (1, 0) => Some(Message::Mapped),
+        (2, 0) => Some(Message::SetupDone),
+        (3, 0) => Some(Message::Launch),
+        (4, _) => SetupError::decode(payload).map(Message::Failed),
+        (5, 4) => Some(Message::Exited(i32::from_le_bytes(
+            payload.try_into().ok()?,
+        ))),
+        _ => None,
+    }
+}
+
+fn malformed() -> SetupError {
+    SetupError {
+        stage: SetupStage::Synchronize,
+        errno: None,
+        message: "malformed message".to_string(),
+    }
+}
+
+// Fills up `buf`, returns false if the pipe is closed before anything is read.
+fn read_exact(fd: RawFd, buf: &mut [u8]) -> CommonResult<bool> {
+    let mut offset = 0;
+    while offset < buf.len() {
+        match unistd::read(fd, &mut buf[offset..]) {
+            Ok(0) if offset == 0 => return Ok(false),
+            Ok(0) => return Err(box std::io::Error::from(std::io::ErrorKind::UnexpectedEof)),
+            Ok(len) => offset += len,
+            Err(nix::Error::Sys(Errno::EINTR)) => {}
+            Err(err) => return Err(box err),
+        }
+    }
+    Ok(true)
+}
+
+fn write_all(fd: RawFd, buf: &[u8]) -> VoidResult {
+    let mut offset = 0;
+    while offset < buf.len() {
+        match unistd::write(fd, &buf[offset..]) {
+            Ok(len) => offset += len,
+            Err(nix::Error::Sys(Errno::EINTR)) => {}
+            Err(err) => return Err(box err),
+        }
+    }
+    Ok(())
+}
+
+pub fn send(fd: RawFd, message: &Message) -> VoidResult {
+    write_all(fd, &encode(message))
+}
+
+// Returns `None` if the pipe is closed between messages.
+pub fn receive(fd: RawFd) -> Result<Option<Message>, SetupError> {
+    let mut header = [0_u8; 5];
+    if !read_exact(fd, &mut header).at_stage(SetupStage::Synchronize)? {
+        return Ok(None);
+    }
+    let length = u32::from_le_bytes(header[1..].try_into().unwrap()) as usize;
+    if length > MAX_PAYLOAD_LENGTH {
+        return Err(malformed());
+    }
+
+    let mut payload = Vec::new();
+    payload.resize(length, 0);
+    if !read_exact(fd, &mut payload).at_stage(SetupStage::Synchronize)? {
+        return Err(malformed());
+    }
+    decode(header[0], &payload).map(Some).ok_or_else(malformed)
+}
+
+// Waits for `expected`, a reported error or anything else is turned into an error.
+pub fn expect(fd: RawFd, expected: Message) -> Result<(), SetupError> {
+    match receive(fd)? {
+        Some(x) if x == expected => Ok(()),
+        Some(Message::Failed(err)) => Err(err),
+        Some(_) => Err(malformed()),
+        None => Err(SetupError {
+            stage: SetupStage::Synchronize,
+            errno: None,
+            message: format!("closed while waiting for {:?}", expected),
+        }),
+    }
+}
+
+// Waits for the other side to execute its target, i.e. for the CLOEXEC pipe to be closed.
+pub fn expect_exec(fd: RawFd) -> Result<(), SetupError> {
+    match receive(fd)? {
+        None => Ok(()),
+        Some(Message::Failed(err)) => Err(err),
+        Some(_) => Err(malformed()),
+    }
+}
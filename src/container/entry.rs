@@ -1,20 +1,30 @@
 use {
-    super::Config,
-    crate::{security::ApplySecurityPolicy, CommonResult, VoidResult},
+    super::{
+        channel::{self, Message},
+        error::{AtStage, SetupError, SetupStage},
+        init, Config,
+    },
+    crate::{
+        filesystem::MountNamespacedFs, security::ApplySecurityPolicy, CommonResult, VoidResult,
+    },
     nix::{
+        errno::Errno,
         mount::{self, MsFlags},
         unistd,
     },
-    std::{ffi::CString, fs, os::unix::io::RawFd, sync::Arc},
+    std::{ffi::CString, fs, io::Read, os::unix::io::RawFd, sync::Arc},
 };
 
-type NeverResult = CommonResult<!>;
+type SetupResult<T> = Result<T, SetupError>;
 
 #[derive(Debug, Clone)]
 pub struct InternalData {
     pub config: Arc<Config>,
-    pub ready_pipe_set: (RawFd, RawFd),
-    pub report_pipe_set: (RawFd, RawFd),
+    pub command_pipe_set: (RawFd, RawFd), // from the parent to the child
+    pub report_pipe_set: (RawFd, RawFd),  // from the child to the parent
+    pub init_pipe_set: Option<(RawFd, RawFd)>, // where the built-in init reports the target's exit
+    pub stdio: [Option<RawFd>; 3],
+    pub pty_slave: Option<RawFd>,
 }
 
 fn set_hostname(hostname: &String) -> VoidResult {
@@ -51,49 +61,112 @@ fn create_rootdir(root: &std::path::Path) -> VoidResult {
     Ok(())
 }
 
-fn mount_filesystem(config: Arc<Config>) -> VoidResult {
+fn mount_filesystem(config: Arc<Config>) -> SetupResult<()> {
     let container_workpath = get_container_workpath(&config.working_path, config.uid);
     let container_rootpath = container_workpath.join("target");
 
-    create_rootdir(&container_rootpath)?;
-    mark_mount_ns_private()?;
+    if !config.prepared_root {
+        create_rootdir(&container_rootpath).at_stage(SetupStage::RootFs)?;
+    }
+    mark_mount_ns_private().at_stage(SetupStage::RootFs)?;
+
+    let stage = |index: usize, x: &Box<dyn MountNamespacedFs>| SetupStage::Mount {
+        index: index,
+        target: x.target(),
+    };
 
     // mounts before chroot
-    for x in config.fs.iter() {
-        x.loading(&container_rootpath, &container_workpath)?;
+    for (index, x) in config.fs.iter().enumerate() {
+        x.loading(&container_rootpath, &container_workpath)
+            .at_stage(stage(index, x))?;
     }
 
     // chroot
-    change_rootpath(&container_rootpath)?;
+    change_rootpath(&container_rootpath).at_stage(SetupStage::RootFs)?;
 
     // mounts after chroot
-    for x in config.fs.iter() {
-        x.loaded()?;
+    for (index, x) in config.fs.iter().enumerate() {
+        x.loaded().at_stage(stage(index, x))?;
     }
 
     Ok(())
 }
 
-fn run_init(config: Arc<Config>) -> NeverResult {
-    let cstyle_target = CString::new(config.target_executable.to_string())?;
-    unistd::execve::<_, CString>(&cstyle_target, &[cstyle_target.as_c_str()], &[])?;
+// The interpreter named on the `#!` line of a script, if it is one. The kernel reads no more than
+// the first 256 bytes either.
+fn script_interpreter(executable: &str) -> Option<String> {
+    let mut buf = [0_u8; 256];
+    let len = fs::File::open(executable)
+        .and_then(|mut file| file.read(&mut buf))
+        .ok()?;
+    let line = buf[..len].strip_prefix(b"#!")?;
+    let line = line.split(|&x| x == b'\n').next()?;
+    let line = String::from_utf8_lossy(line);
+    line.split_whitespace().next().map(str::to_string)
+}
 
-    unreachable!()
+// Tells why execve() failed, as the errno alone is misleading in some cases, e.g. ENOENT for an
+// executable which does exist but whose interpreter does not.
+fn exec_error(executable: &str, err: nix::Error) -> SetupError {
+    let reason = match err.as_errno() {
+        Some(Errno::ENOENT) if std::path::Path::new(executable).exists() => {
+            match script_interpreter(executable) {
+                Some(x) => format!(" (its interpreter {} is missing)", x),
+                None => " (its ELF interpreter is missing)".to_string(),
+            }
+        }
+        Some(Errno::ENOEXEC) => " (unknown format, or built for another architecture)".to_string(),
+        Some(Errno::EACCES) => " (not a regular file, or not executable)".to_string(),
+        _ => String::new(),
+    };
+    SetupError {
+        stage: SetupStage::Exec,
+        errno: err.as_errno(),
+        message: format!("failed to execute {}: {}{}", executable, err, reason),
+    }
 }
 
-fn block_until_ready(p: RawFd) -> VoidResult {
-    unistd::read(p, &mut Vec::new())?;
-    Ok(())
+// Only returns if executing fails.
+pub(super) fn execute(executable: &str, args: &[String], environment: &[String]) -> SetupError {
+    let res = (|| -> CommonResult<(CString, Vec<CString>, Vec<CString>)> {
+        let cstyle_target = CString::new(executable)?;
+        let mut cstyle_args = vec![cstyle_target.clone()];
+        for arg in args.iter() {
+            cstyle_args.push(CString::new(arg.to_string())?);
+        }
+        let mut cstyle_env = Vec::new();
+        for var in environment.iter() {
+            cstyle_env.push(CString::new(var.to_string())?);
+        }
+        Ok((cstyle_target, cstyle_args, cstyle_env))
+    })();
+    let (cstyle_target, cstyle_args, cstyle_env) = match res {
+        Ok(x) => x,
+        Err(err) => return SetupError::new(SetupStage::Exec, &*err),
+    };
+
+    match unistd::execve::<_, CString>(&cstyle_target, &cstyle_args, &cstyle_env) {
+        Err(err) => exec_error(executable, err),
+        _ => unreachable!(),
+    }
 }
 
-fn apply_security_policy(policies: &Vec<Box<dyn ApplySecurityPolicy>>) -> VoidResult {
+pub(super) fn apply_security_policy(
+    policies: &Vec<Box<dyn ApplySecurityPolicy>>,
+) -> SetupResult<()> {
     for policy in policies.iter() {
-        policy.apply()?;
+        policy
+            .apply()
+            .at_stage(SetupStage::SecurityPolicy(policy.name().to_string()))?;
     }
     Ok(())
 }
 
-fn redirect_standard_io(config: Arc<Config>) -> VoidResult {
+fn redirect_standard_io(
+    config: Arc<Config>,
+    pty_slave: Option<RawFd>,
+    pipes: [Option<RawFd>; 3],
+) -> VoidResult {
     const STDIN_FN: RawFd = 0;
     const STDOUT_FN: RawFd = 1;
     const STDERR_FN: RawFd = 2;
@@ -116,11 +189,27 @@ fn redirect_standard_io(config: Arc<Config>) -> VoidResult {
     }
 
     fn do_redirect(source: RawFd, target: RawFd) -> VoidResult {
+        if source == target {
+            use nix::fcntl::{fcntl, FcntlArg, FdFlag};
+            fcntl(source, FcntlArg::F_SETFD(FdFlag::empty()))?;
+            return Ok(());
+        }
         unistd::dup2(source, target)?;
         unistd::close(source)?;
         Ok(())
     }
 
+    if let Some(slave) = pty_slave {
+        super::pty::attach(slave)?;
+    }
+
+    // pipes owned by the parent go after the pty, paths set in config take precedence over them
+    for (target, source) in [STDIN_FN, STDOUT_FN, STDERR_FN].iter().zip(pipes.iter()) {
+        if let Some(source) = source {
+            do_redirect(*source, *target)?;
+        }
+    }
+
     if let Some(p) = &config.stdin {
         let fd = open_input(std::path::PathBuf::from(p))?;
         do_redirect(fd, STDIN_FN)?;
@@ -139,42 +228,114 @@ fn redirect_standard_io(config: Arc<Config>) -> VoidResult {
     Ok(())
 }
 
+// Signals ignored by the parent stay ignored across execve(), the target should start with the
+// default dispositions instead (e.g. to be killed by SIGPIPE when writing to a closed pipe).
+pub(super) fn reset_signal_dispositions() -> VoidResult {
+    use nix::sys::signal::{self, SigHandler, Signal};
+    unsafe { signal::signal(Signal::SIGPIPE, SigHandler::SigDfl)? };
+    Ok(())
+}
+
+// Forks the target off the built-in init, which stays behind as pid 1.
+// Only returns in the target.
+fn start_builtin_init(command_pipe: RawFd, report_pipe: RawFd, init_pipe: RawFd) -> VoidResult {
+    let origin_mask = init::block_signals()?;
+    match unsafe { unistd::fork()? } {
+        unistd::ForkResult::Child => {
+            unistd::close(init_pipe)?;
+            init::restore_signals(&origin_mask)
+        }
+        unistd::ForkResult::Parent { child } => {
+            // standard IO belongs to the target, keeping it open would delay EOF seen by others,
+            // and so would the report pipe, which the parent waits to be closed by execve()
+            for fd in [command_pipe, report_pipe, 0, 1, 2].iter() {
+                let _ = unistd::close(*fd);
+            }
+            init::run(child, init_pipe)
+        }
+    }
+}
+
+// Closes every file descriptor except those in `keep`.
+// File descriptors opened by other threads of the parent are inherited by clone(), some of which
+// belong to other containers and would delay EOF seen by them if kept.
+pub(super) fn close_fds_except(keep: &[RawFd]) -> VoidResult {
+    let fds: Vec<RawFd> = fs::read_dir("/proc/self/fd")?
+        .filter_map(|x| x.ok()?.file_name().to_str()?.parse().ok())
+        .collect();
+    for fd in fds.into_iter().filter(|x| !keep.contains(x)) {
+        // one of them belonged to read_dir() and has been closed already
+        let _ = unistd::close(fd);
+    }
+    Ok(())
+}
+
 fn check_init(config: Arc<Config>) -> VoidResult {
     unistd::access::<str>(&config.target_executable, unistd::AccessFlags::X_OK)?;
     Ok(())
 }
 
-fn exceptable_main(config: Arc<Config>, ready_pipe: RawFd, report_pipe: RawFd) -> NeverResult {
-    set_hostname(&config.hostname)?;
-    redirect_standard_io(config.clone())?;
+fn exceptable_main(
+    config: Arc<Config>,
+    command_pipe: RawFd,
+    report_pipe: RawFd,
+    init_pipe: Option<RawFd>,
+    pty_slave: Option<RawFd>,
+    stdio: [Option<RawFd>; 3],
+) -> SetupResult<!> {
+    // nothing can be done before the parent maps ids, e.g. files would be created by nobody
+    channel::send(report_pipe, &Message::Ready).at_stage(SetupStage::Synchronize)?;
+    channel::expect(command_pipe, Message::Mapped)?;
+
+    set_hostname(&config.hostname).at_stage(SetupStage::Hostname)?;
+    redirect_standard_io(config.clone(), pty_slave, stdio).at_stage(SetupStage::RedirectIo)?;
     mount_filesystem(config.clone())?;
+    config.rlimits.apply().at_stage(SetupStage::Rlimit)?;
+    if let Some(init_pipe) = init_pipe {
+        start_builtin_init(command_pipe, report_pipe, init_pipe).at_stage(SetupStage::Init)?;
+    }
     apply_security_policy(&config.security_policies)?;
-    check_init(config.clone())?;
+    check_init(config.clone()).at_stage(SetupStage::AccessCheck)?;
+    reset_signal_dispositions().at_stage(SetupStage::Synchronize)?;
 
-    block_until_ready(ready_pipe)?;
-    unistd::write(report_pipe, &[0])?;
-    run_init(config)
+    channel::send(report_pipe, &Message::SetupDone).at_stage(SetupStage::Synchronize)?;
+    channel::expect(command_pipe, Message::Launch)?;
+    // the report pipe is CLOEXEC, its closing tells the parent the target has been executed,
+    // otherwise the reason of the failure is sent over it
+    Err(execute(
+        &config.target_executable,
+        &config.args,
+        &config.environment,
+    ))
 }
 
-fn extract_pipes(rd_set: (RawFd, RawFd), rp_set: (RawFd, RawFd)) -> CommonResult<(RawFd, RawFd)> {
-    let (rd_read, rd_write) = rd_set;
-    let (rp_read, rp_write) = rp_set;
-    unistd::close(rd_write)?;
-    unistd::close(rp_read)?;
-    Ok((rd_read, rp_write))
+// Keeps only the ends of pipes used by the child.
+fn extract_pipes(cfg: &InternalData) -> VoidResult {
+    let mut keep = vec![0, 1, 2, cfg.command_pipe_set.0, cfg.report_pipe_set.1];
+    keep.extend(cfg.init_pipe_set.map(|(_, write)| write));
+    keep.extend(cfg.stdio.iter().flatten());
+    keep.extend(cfg.pty_slave);
+    close_fds_except(&keep)
 }
 
 #[allow(unused_must_use)]
 pub fn main(cfg: InternalData) -> isize {
-    let (ready_pipe, report_pipe) = extract_pipes(cfg.ready_pipe_set, cfg.report_pipe_set).unwrap();
-    match exceptable_main(cfg.config, ready_pipe, report_pipe) {
+    let report_pipe = cfg.report_pipe_set.1;
+    let res = extract_pipes(&cfg)
+        .at_stage(SetupStage::Synchronize)
+        .and_then(|_| {
+            exceptable_main(
+                cfg.config,
+                cfg.command_pipe_set.0,
+                report_pipe,
+                cfg.init_pipe_set.map(|(_, write)| write),
+                cfg.pty_slave,
+                cfg.stdio,
+            )
+        });
+    match res {
         Err(err) => {
-            println!("Entry Error:\n{}\nEnd.\n", err);
-            unistd::write(report_pipe, &[1]);
-            let report_msg = format!("{}", err).to_string();
-            let report_msg_buf = report_msg.as_bytes();
-            unistd::write(report_pipe, &report_msg_buf.len().to_ne_bytes());
-            unistd::write(report_pipe, report_msg_buf);
+            channel::send(report_pipe, &Message::Failed(err));
             -1
         }
         _ => unreachable!(),
@@ -1,37 +1,261 @@
-use std::fmt;
+use {
+    crate::CommonResult,
+    nix::errno::Errno,
+    std::{convert::TryInto, fmt},
+};
 
 #[derive(Debug)]
 pub enum Error {
     ForkFailed(nix::Error),
     AlreadyStarted,
-    EntryError(EntryError),
+    NotStarted,
+    StdioKindChanged,
+    SetupFailed(SetupError),
 }
 
-#[derive(Debug)]
-pub struct EntryError {
-    error_code: u8,
-    additional_info: String,
+impl From<SetupError> for Error {
+    fn from(x: SetupError) -> Self {
+        Error::SetupFailed(x)
+    }
 }
 
-impl EntryError {
-    pub fn new(code: u8, buf: &[u8]) -> Self {
-        Self {
-            error_code: code,
-            additional_info: String::from_utf8_lossy(buf).into_owned(),
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl Error {
+    // Takes the error of a container back out of the boxed one returned by `Container::start()`,
+    // `prepare()`, `launch()` or `exec()`, e.g. to match on the stage of a `SetupFailed`. Other
+    // errors, such as those of I/O in the parent, are given back as they are.
+    pub fn from_boxed(err: Box<dyn std::error::Error>) -> Result<Self, Box<dyn std::error::Error>> {
+        err.downcast::<Self>().map(|x| *x)
+    }
+}
+
+// The step of setting up a container which failed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SetupStage {
+    Hostname,
+    RedirectIo,
+    RootFs,                                 // creating the root directory or changing into it
+    Mount { index: usize, target: String }, // the `index`-th entry of `Config::fs`
+    Rlimit,
+    Init, // starting the built-in init
+    SecurityPolicy(String),
+    AccessCheck,
+    Synchronize, // talking with the parent
+    IdMapping,
+    CGroup(String),    // name of the controller
+    Namespace(String), // joining a namespace of a running container
+    Exec,
+}
+
+// Error happened while setting up a container, either in the parent or in the container.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SetupError {
+    pub stage: SetupStage,
+    pub errno: Option<Errno>,
+    pub message: String,
+}
+
+// Looks for the errno behind an error, following its sources.
+fn find_errno(err: &(dyn std::error::Error + 'static)) -> Option<Errno> {
+    let mut current = Some(err);
+    while let Some(err) = current {
+        if let Some(x) = err.downcast_ref::<nix::Error>().and_then(|x| x.as_errno()) {
+            return Some(x);
+        }
+        if let Some(x) = err
+            .downcast_ref::<std::io::Error>()
+            .and_then(|x| x.raw_os_error())
+        {
+            return Some(Errno::from_i32(x));
         }
+        current = err.source();
     }
+    None
 }
 
-impl std::convert::Into<Error> for EntryError {
-    fn into(self) -> Error {
-        Error::EntryError(self)
+// Cursor over an encoded `SetupError`.
+struct Decoder<'a> {
+    buf: &'a [u8],
+}
+
+impl<'a> Decoder<'a> {
+    fn bytes(&mut self, len: usize) -> Option<&'a [u8]> {
+        if self.buf.len() < len {
+            return None;
+        }
+        let (res, rest) = self.buf.split_at(len);
+        self.buf = rest;
+        Some(res)
+    }
+
+    fn u8(&mut self) -> Option<u8> {
+        self.bytes(1).map(|x| x[0])
+    }
+
+    fn u32(&mut self) -> Option<u32> {
+        Some(u32::from_le_bytes(self.bytes(4)?.try_into().ok()?))
+    }
+
+    fn string(&mut self) -> Option<String> {
+        let len = self.u32()? as usize;
+        Some(String::from_utf8_lossy(self.bytes(len)?).into_owned())
     }
 }
 
-impl fmt::Display for Error {
+fn encode_string(buf: &mut Vec<u8>, x: &str) {
+    buf.extend_from_slice(&(x.len() as u32).to_le_bytes());
+    buf.extend_from_slice(x.as_bytes());
+}
+
+impl SetupError {
+    pub fn new(stage: SetupStage, err: &(dyn std::error::Error + 'static)) -> Self {
+        Self {
+            stage: stage,
+            errno: find_errno(err),
+            message: err.to_string(),
+        }
+    }
+
+    // The encoding is stable: a tag byte of the stage followed by its fields, the errno (0 if
+    // there is none) and the message. Integers are little-endian, and strings are prefixed by
+    // their length as u32.
+    pub fn encode(&self) -> Vec<u8> {
+        let mut res = Vec::new();
+        match &self.stage {
+            SetupStage::Hostname => res.push(0),
+            SetupStage::RedirectIo => res.push(1),
+            SetupStage::RootFs => res.push(2),
+            SetupStage::Mount { index, target } => {
+                res.push(3);
+                res.extend_from_slice(&(*index as u32).to_le_bytes());
+                encode_string(&mut res, target);
+            }
+            SetupStage::Rlimit => res.push(4),
+            SetupStage::Init => res.push(5),
+            SetupStage::SecurityPolicy(name) => {
+                res.push(6);
+                encode_string(&mut res, name);
+            }
+            SetupStage::AccessCheck => res.push(7),
+            SetupStage::Synchronize => res.push(8),
+            SetupStage::IdMapping => res.push(9),
+            SetupStage::CGroup(name) => {
+                res.push(10);
+                encode_string(&mut res, name);
+            }
+            SetupStage::Namespace(name) => {
+                res.push(11);
+                encode_string(&mut res, name);
+            }
+            SetupStage::Exec => res.push(12),
+        }
+        let errno = self.errno.map_or(0, |x| x as i32);
+        res.extend_from_slice(&errno.to_le_bytes());
+        encode_string(&mut res, &self.message);
+        res
+    }
+
+    pub fn decode(buf: &[u8]) -> Option<Self> {
+        let mut decoder = Decoder { buf: buf };
+        let stage = match decoder.u8()? {
+            0 => SetupStage::Hostname,
+            1 => SetupStage::RedirectIo,
+            2 => SetupStage::RootFs,
+            3 => SetupStage::Mount {
+                index: decoder.u32()? as usize,
+                target: decoder.string()?,
+            },
+            4 => SetupStage::Rlimit,
+            5 => SetupStage::Init,
+            6 => SetupStage::SecurityPolicy(decoder.string()?),
+            7 => SetupStage::AccessCheck,
+            8 => SetupStage::Synchronize,
+            9 => SetupStage::IdMapping,
+            10 => SetupStage::CGroup(decoder.string()?),
+            11 => SetupStage::Namespace(decoder.string()?),
+            12 => SetupStage::Exec,
+            _ => return None,
+        };
+        let errno = match decoder.u32()? as i32 {
+            0 => None,
+            x => Some(Errno::from_i32(x)),
+        };
+        Some(Self {
+            stage: stage,
+            errno: errno,
+            message: decoder.string()?,
+        })
+    }
+}
+
+impl fmt::Display for SetupError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        write!(f, "{:?}", self)
+        write!(f, "setup failed at {:?}: {}", self.stage, self.message)
     }
 }
 
-impl std::error::Error for Error {}
+impl std::error::Error for SetupError {}
+
+// Tags the error of a setup step with its stage.
+pub(crate) trait AtStage<T> {
+    fn at_stage(self, stage: SetupStage) -> Result<T, SetupError>;
+}
+
+impl<T> AtStage<T> for CommonResult<T> {
+    fn at_stage(self, stage: SetupStage) -> Result<T, SetupError> {
+        self.map_err(|err| SetupError::new(stage, &*err))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{
+            container::{Config, Container},
+            filesystem::MountReadOnlyBindFs,
+        },
+    };
+
+    #[test]
+    fn from_boxed() {
+        let err = nix::Error::from_errno(Errno::ENOENT);
+        let boxed: Box<dyn std::error::Error> =
+            Box::new(Error::from(SetupError::new(SetupStage::Exec, &err)));
+        match Error::from_boxed(boxed) {
+            Ok(Error::SetupFailed(SetupError { stage, errno, .. })) => {
+                assert_eq!(stage, SetupStage::Exec);
+                assert_eq!(errno, Some(Errno::ENOENT));
+            }
+            x => panic!("unexpected {:?}", x),
+        }
+
+        let boxed: Box<dyn std::error::Error> = Box::new(std::io::Error::from_raw_os_error(1));
+        assert!(Error::from_boxed(boxed).is_err());
+    }
+
+    #[test]
+    #[ignore] // needs root
+    fn missing_executable() {
+        let mut config: Config = Default::default();
+        config.target_executable = "/nonexistent".to_string();
+        config
+            .fs
+            .push(Box::new(MountReadOnlyBindFs::from("/".to_string())));
+        let mut container = Container::from(config);
+        match container.start().map_err(Error::from_boxed) {
+            Err(Ok(Error::SetupFailed(SetupError { stage, errno, .. }))) => {
+                assert_eq!(stage, SetupStage::AccessCheck);
+                assert_eq!(errno, Some(Errno::ENOENT));
+            }
+            x => panic!("unexpected {:?}", x),
+        }
+    }
+}
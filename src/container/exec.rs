@@ -0,0 +1,310 @@
+use {
+    super::{
+        channel::{self, Message},
+        entry,
+        error::{self, AtStage, SetupError, SetupStage},
+        init,
+        io::{StdioHandles, StdioPipes},
+        process::Process,
+        Config, ExitReport, ExitStatus, Stdio,
+    },
+    crate::{CommonResult, VoidResult},
+    nix::{
+        fcntl::{self, OFlag},
+        sched::{self, CloneFlags},
+        sys::{
+            signal::{self, SigSet, SigmaskHow, Signal},
+            stat::Mode,
+            wait::WaitPidFlag,
+        },
+        unistd::{self, Pid},
+    },
+    std::{convert::TryFrom, os::unix::io::RawFd, sync::Arc},
+};
+
+type SetupResult<T> = Result<T, SetupError>;
+
+// The user namespace goes first, joining it grants the capabilities needed to join the others.
+const NAMESPACES: [(&str, CloneFlags); 5] = [
+    ("user", CloneFlags::CLONE_NEWUSER),
+    ("ipc", CloneFlags::CLONE_NEWIPC),
+    ("uts", CloneFlags::CLONE_NEWUTS),
+    ("pid", CloneFlags::CLONE_NEWPID),
+    ("mnt", CloneFlags::CLONE_NEWNS),
+];
+
+struct ExecData<'a> {
+    config: &'a Config,
+    container_pid: Pid,
+    executable: &'a str,
+    args: &'a [String],
+    environment: &'a [String],
+    stdio: [Option<RawFd>; 3],
+    report_pipe: RawFd,
+}
+
+fn open_proc_file(pid: Pid, name: &str, flags: OFlag) -> CommonResult<RawFd> {
+    let path = format!("/proc/{}/{}", pid, name);
+    Ok(fcntl::open(
+        path.as_str(),
+        flags | OFlag::O_RDONLY | OFlag::O_CLOEXEC,
+        Mode::empty(),
+    )?)
+}
+
+fn join_container(data: &ExecData) -> SetupResult<()> {
+    // the cgroup filesystem is only reachable before joining the mount namespace
+    data.config
+        .cgroup_limits
+        .attach(data.config.uid, unistd::getpid())
+        .at_stage(SetupStage::CGroup("cgroup".to_string()))?;
+
+    // the container is chrooted, its root directory is not the one of its mount namespace
+    let root = open_proc_file(data.container_pid, "root", OFlag::O_DIRECTORY)
+        .at_stage(SetupStage::RootFs)?;
+    let mut namespaces = Vec::new();
+    for (name, flag) in NAMESPACES.iter() {
+        let stage = SetupStage::Namespace(name.to_string());
+        let fd = open_proc_file(data.container_pid, &format!("ns/{}", name), OFlag::empty())
+            .at_stage(stage.clone())?;
+        namespaces.push((fd, *flag, stage));
+    }
+
+    for (fd, flag, stage) in namespaces.into_iter() {
+        (|| -> VoidResult {
+            sched::setns(fd, flag)?;
+            unistd::close(fd)?;
+            Ok(())
+        })()
+        .at_stage(stage)?;
+    }
+
+    (|| -> VoidResult {
+        unistd::fchdir(root)?;
+        unistd::chroot(".")?;
+        unistd::chdir("/")?;
+        unistd::close(root)?;
+        Ok(())
+    })()
+    .at_stage(SetupStage::RootFs)
+}
+
+fn redirect_standard_io(stdio: &[Option<RawFd>; 3]) -> VoidResult {
+    for (target, source) in stdio.iter().enumerate() {
+        if let Some(source) = source {
+            if *source == target as RawFd {
+                use nix::fcntl::{FcntlArg, FdFlag};
+                fcntl::fcntl(*source, FcntlArg::F_SETFD(FdFlag::empty()))?;
+            } else {
+                unistd::dup2(*source, target as RawFd)?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Only returns if executing fails.
+fn exec_target(data: &ExecData) -> SetupResult<()> {
+    // the target must not outlive the process forwarding signals to it
+    nix::errno::Errno::result(unsafe {
+        libc::prctl(libc::PR_SET_PDEATHSIG, libc::SIGKILL, 0, 0, 0)
+    })
+    .map_err(|e| SetupError::new(SetupStage::Synchronize, &e))?;
+    if unistd::getppid() == Pid::from_raw(1) {
+        unsafe { libc::_exit(1) };
+    }
+
+    redirect_standard_io(&data.stdio).at_stage(SetupStage::RedirectIo)?;
+    data.config.rlimits.apply().at_stage(SetupStage::Rlimit)?;
+    entry::apply_security_policy(&data.config.security_policies)?;
+    entry::reset_signal_dispositions().at_stage(SetupStage::Synchronize)?;
+    Err(entry::execute(data.executable, data.args, data.environment))
+}
+
+// Exits the same way as the process with the raw wait status `status` did.
+fn exit_like(status: i32) -> ! {
+    if libc::WIFSIGNALED(status) {
+        if let Ok(sig) = Signal::try_from(libc::WTERMSIG(status)) {
+            let mut set = SigSet::empty();
+            set.add(sig);
+            let _ = signal::sigprocmask(SigmaskHow::SIG_UNBLOCK, Some(&set), None);
+            let _ = unsafe { signal::signal(sig, signal::SigHandler::SigDfl) };
+            let _ = signal::raise(sig);
+        }
+    }
+    unsafe { libc::_exit(libc::WEXITSTATUS(status)) }
+}
+
+// Runs in a process forked by the parent, which joins the container, forks the target there (the
+// PID namespace only applies to children), and then stays outside to forward signals to it.
+fn forwarder_main(data: &ExecData) -> isize {
+    let mut keep = vec![0, 1, 2, data.report_pipe];
+    keep.extend(data.stdio.iter().flatten());
+    let origin_mask = match entry::close_fds_except(&keep)
+        .at_stage(SetupStage::Synchronize)
+        .and_then(|_| join_container(data))
+        .and_then(|_| init::block_signals().at_stage(SetupStage::Synchronize))
+    {
+        Ok(x) => x,
+        Err(err) => {
+            let _ = channel::send(data.report_pipe, &Message::Failed(err));
+            return -1;
+        }
+    };
+
+    match unsafe { unistd::fork() } {
+        Ok(unistd::ForkResult::Child) => {
+            let res = init::restore_signals(&origin_mask)
+                .at_stage(SetupStage::Synchronize)
+                .and_then(|_| exec_target(data));
+            if let Err(err) = res {
+                let _ = channel::send(data.report_pipe, &Message::Failed(err));
+            }
+            unsafe { libc::_exit(-1) }
+        }
+        Ok(unistd::ForkResult::Parent { child }) => {
+            // the report pipe gets closed once the target executes, telling the parent it's done
+            let _ = unistd::close(data.report_pipe);
+            for fd in data.stdio.iter().flatten() {
+                let _ = unistd::close(*fd);
+            }
+            exit_like(init::supervise(child))
+        }
+        Err(err) => {
+            let err = SetupError::new(SetupStage::Synchronize, &err);
+            let _ = channel::send(data.report_pipe, &Message::Failed(err));
+            -1
+        }
+    }
+}
+
+pub(super) fn exec(
+    config: &Config,
+    container_pid: Pid,
+    executable: &str,
+    args: &[String],
+    environment: &[String],
+    io: Stdio,
+) -> CommonResult<ExecProcess> {
+    const STACK_SIZE: usize = 2 * 1024 * 1024; // 2048kb
+
+    let mut stack_memory = Vec::new();
+    stack_memory.resize(STACK_SIZE, 0);
+
+    let (report_pipe_read, report_pipe_write) = unistd::pipe2(OFlag::O_CLOEXEC)?;
+    let mut stdio_pipes = StdioPipes::new(io.stdin, io.stdout, io.stderr)?;
+    let data = ExecData {
+        config: config,
+        container_pid: container_pid,
+        executable: executable,
+        args: args,
+        environment: environment,
+        stdio: stdio_pipes.child,
+        report_pipe: report_pipe_write,
+    };
+
+    let pid = nix::sched::clone(
+        box || forwarder_main(&data),
+        stack_memory.as_mut(),
+        CloneFlags::empty(),
+        Some(signal::SIGCHLD as i32),
+    );
+    unistd::close(report_pipe_write)?;
+    stdio_pipes.close_child_ends()?;
+    let pid = match pid {
+        Ok(x) => x,
+        Err(e) => {
+            unistd::close(report_pipe_read)?;
+            return Err(box error::Error::ForkFailed(e));
+        }
+    };
+
+    let process = Arc::new(Process::new(pid));
+    let limited_process = process.clone();
+    let handles = stdio_pipes.spawn(None, None, move || {
+        let _ = limited_process.signal(signal::SIGKILL);
+    })?;
+    let mut res = ExecProcess {
+        process: process,
+        stdio_handles: Some(handles),
+        report: None,
+    };
+
+    // nothing but EOF means the target has been executed
+    let exec_res = channel::expect_exec(report_pipe_read);
+    unistd::close(report_pipe_read)?;
+    if let Err(err) = exec_res {
+        res.wait()?;
+        return Err(box error::Error::from(err));
+    }
+    Ok(res)
+}
+
+// A process started by `Container::exec()` in a running container. Its pid is the one of a
+// process outside the container which forwards signals to the target and exits the same way.
+#[derive(Debug)]
+pub struct ExecProcess {
+    process: Arc<Process>,
+    stdio_handles: Option<StdioHandles>,
+    report: Option<ExitReport>,
+}
+
+impl ExecProcess {
+    pub fn signal(&self, sig: Signal) -> VoidResult {
+        self.process.signal(sig)
+    }
+
+    pub fn wait(&mut self) -> CommonResult<ExitReport> {
+        loop {
+            if let Some(report) = self.wait_with_flags(None)? {
+                return Ok(report);
+            }
+        }
+    }
+
+    // Returns `None` if the process is still running.
+    pub fn try_wait(&mut self) -> CommonResult<Option<ExitReport>> {
+        self.wait_with_flags(Some(WaitPidFlag::WNOHANG))
+    }
+
+    fn wait_with_flags(&mut self, flags: Option<WaitPidFlag>) -> CommonResult<Option<ExitReport>> {
+        if let Some(report) = &self.report {
+            return Ok(Some(report.clone()));
+        }
+
+        let status = match self.process.reap(flags)? {
+            Some(status) => status,
+            None => return Ok(None),
+        };
+        let status = match ExitStatus::from_wait_status(status) {
+            Some(status) => status,
+            None => return Ok(None),
+        };
+
+        let (stdout, stderr) = match self.stdio_handles.take() {
+            Some(handles) => handles.join(),
+            None => Default::default(),
+        };
+        let report = ExitReport {
+            status: status,
+            time_limit_exceeded: false,
+            output_limit_exceeded: stdout.limit_exceeded || stderr.limit_exceeded,
+            stdout: stdout.data,
+            stderr: stderr.data,
+            cgroup_events: Default::default(), // they belong to the container, as the IO does
+            io_usage: Default::default(),
+        };
+        self.report = Some(report.clone());
+        Ok(Some(report))
+    }
+}
+
+impl Drop for ExecProcess {
+    #[allow(unused_must_use)]
+    fn drop(&mut self) {
+        if self.report.is_none() {
+            self.process.signal(signal::SIGKILL);
+            self.wait();
+        }
+    }
+}
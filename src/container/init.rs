@@ -0,0 +1,100 @@
+use {
+    super::channel::{self, Message},
+    crate::{CommonResult, VoidResult},
+    nix::{
+        sys::{
+            signal::{self, SigSet, SigmaskHow, Signal},
+            wait::WaitStatus,
+        },
+        unistd::{self, Pid},
+    },
+    std::os::unix::io::RawFd,
+};
+
+// Signals waited for by the init, which are SIGCHLD and those to be forwarded to the target.
+// Faults and signals which can not be blocked are left out.
+fn forwarded_signals() -> SigSet {
+    let mut res = SigSet::all();
+    for sig in [
+        Signal::SIGKILL,
+        Signal::SIGSTOP,
+        Signal::SIGSEGV,
+        Signal::SIGBUS,
+        Signal::SIGFPE,
+        Signal::SIGILL,
+    ]
+    .iter()
+    {
+        res.remove(*sig);
+    }
+    res
+}
+
+// Blocks the signals to be forwarded, must be called before forking the target.
+// Returns the original signal mask, which the target should restore.
+pub fn block_signals() -> CommonResult<SigSet> {
+    let mut origin = SigSet::empty();
+    signal::sigprocmask(
+        SigmaskHow::SIG_BLOCK,
+        Some(&forwarded_signals()),
+        Some(&mut origin),
+    )?;
+    Ok(origin)
+}
+
+pub fn restore_signals(origin: &SigSet) -> VoidResult {
+    signal::sigprocmask(SigmaskHow::SIG_SETMASK, Some(origin), None)?;
+    Ok(())
+}
+
+// Reaps every exited child, returns the wait status of the target if it is one of them.
+fn reap_children(target: Pid) -> Option<i32> {
+    let mut res = None;
+    loop {
+        let mut status = 0;
+        match unsafe { libc::waitpid(-1, &mut status, libc::WNOHANG) } {
+            pid if pid <= 0 => return res,
+            pid if pid == target.as_raw() => res = Some(status),
+            _ => {}
+        }
+    }
+}
+
+// Reaps zombies and forwards signals to the target, until the target exits.
+// Returns the raw wait status of the target.
+pub fn supervise(target: Pid) -> i32 {
+    let signals = forwarded_signals();
+    loop {
+        match signals.wait() {
+            Ok(Signal::SIGCHLD) => {
+                if let Some(status) = reap_children(target) {
+                    return status;
+                }
+            }
+            Ok(sig) => {
+                let _ = signal::kill(target, sig);
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+// Main loop of the init process.
+// Once the target exits, its raw wait status is sent to `report_pipe`, and the init exits,
+// which kills every process left in the PID namespace.
+pub fn run(target: Pid, report_pipe: RawFd) -> ! {
+    let status = supervise(target);
+    let _ = channel::send(report_pipe, &Message::Exited(status));
+    unsafe { libc::_exit(0) }
+}
+
+// Called by the parent after the init exits, reads the wait status of the target sent by `run()`.
+// Returns `None` if the init was killed before the target exited.
+pub fn read_report(report_pipe: RawFd, pid: Pid) -> CommonResult<Option<WaitStatus>> {
+    let message = channel::receive(report_pipe);
+    unistd::close(report_pipe)?;
+    match message? {
+        Some(Message::Exited(status)) => Ok(Some(WaitStatus::from_raw(pid, status)?)),
+        _ => Ok(None),
+    }
+}
@@ -0,0 +1,479 @@
+use {
+    crate::{CommonResult, VoidResult},
+    nix::{
+        fcntl::{self, FcntlArg, FdFlag, OFlag},
+        poll::{self, PollFd, PollFlags},
+        sys::stat::{self, SFlag},
+        unistd,
+    },
+    std::{
+        fmt,
+        fs::{File, OpenOptions},
+        io::{Read, Write},
+        os::unix::{
+            fs::OpenOptionsExt,
+            io::{AsRawFd, FromRawFd, IntoRawFd, OwnedFd, RawFd},
+        },
+        path::PathBuf,
+        sync::Arc,
+        thread::JoinHandle,
+        time::Duration,
+    },
+};
+
+pub enum Input {
+    Inherit,
+    Bytes(Vec<u8>),
+    Reader(Box<dyn Read + Send>),
+    Fd(OwnedFd),
+    RawFd(RawFd),      // duplicated, the caller still owns it
+    HostPath(PathBuf), // opened by the parent, outside of the container
+}
+
+pub enum Output {
+    Inherit,
+    Capture(usize), // capture into memory, up to the given number of bytes
+    Writer(Box<dyn Write + Send>),
+    Fd(OwnedFd),
+    RawFd(RawFd),      // duplicated, the caller still owns it
+    HostPath(PathBuf), // created or truncated by the parent, outside of the container
+}
+
+impl Default for Input {
+    fn default() -> Self {
+        Input::Inherit
+    }
+}
+
+impl Default for Output {
+    fn default() -> Self {
+        Output::Inherit
+    }
+}
+
+impl fmt::Debug for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Input::Inherit => write!(f, "Inherit"),
+            Input::Bytes(x) => write!(f, "Bytes({} bytes)", x.len()),
+            Input::Reader(_) => write!(f, "Reader"),
+            Input::Fd(x) => write!(f, "Fd({})", x.as_raw_fd()),
+            Input::RawFd(x) => write!(f, "RawFd({})", x),
+            Input::HostPath(x) => write!(f, "HostPath({:?})", x),
+        }
+    }
+}
+
+impl fmt::Debug for Output {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Output::Inherit => write!(f, "Inherit"),
+            Output::Capture(limit) => write!(f, "Capture({})", limit),
+            Output::Writer(_) => write!(f, "Writer"),
+            Output::Fd(x) => write!(f, "Fd({})", x.as_raw_fd()),
+            Output::RawFd(x) => write!(f, "RawFd({})", x),
+            Output::HostPath(x) => write!(f, "HostPath({:?})", x),
+        }
+    }
+}
+
+impl From<File> for Input {
+    fn from(x: File) -> Self {
+        Input::Fd(x.into())
+    }
+}
+
+impl From<File> for Output {
+    fn from(x: File) -> Self {
+        Output::Fd(x.into())
+    }
+}
+
+// Standard IO of a process started by `Container::exec()`.
+#[derive(Debug, Default)]
+pub struct Stdio {
+    pub stdin: Input,
+    pub stdout: Output,
+    pub stderr: Output,
+}
+
+#[derive(Debug, Default)]
+pub struct CapturedOutput {
+    pub data: Vec<u8>,
+    pub limit_exceeded: bool,
+}
+
+fn make_pipe() -> CommonResult<(RawFd, RawFd)> {
+    Ok(unistd::pipe2(OFlag::O_CLOEXEC)?)
+}
+
+fn dup_cloexec(fd: RawFd) -> CommonResult<RawFd> {
+    Ok(fcntl::fcntl(fd, FcntlArg::F_DUPFD_CLOEXEC(0))?)
+}
+
+fn into_cloexec(fd: OwnedFd) -> CommonResult<RawFd> {
+    let fd = fd.into_raw_fd();
+    fcntl::fcntl(fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+    Ok(fd)
+}
+
+// A file the container writes an output to directly, without passing through the parent.
+#[derive(Debug)]
+enum OutputFile {
+    Fd(File, u64), // a duplicate of the fd handed to the container, and its initial length
+    Path(PathBuf), // opened, and truncated, by the container itself
+}
+
+impl OutputFile {
+    fn written(&self) -> u64 {
+        match self {
+            OutputFile::Fd(file, initial_len) => file
+                .metadata()
+                .map_or(0, |x| x.len().saturating_sub(*initial_len)),
+            OutputFile::Path(path) => std::fs::metadata(path).map_or(0, |x| x.len()),
+        }
+    }
+}
+
+// The parent side of an output stream.
+#[derive(Debug)]
+enum OutputSide {
+    Pipe(Output, File),
+    File(Arc<OutputFile>),
+}
+
+impl OutputSide {
+    // Only regular files are kept open by the parent to be watched. Holding a pipe or a socket
+    // would keep the reader from seeing EOF until the container is waited for, and their growth
+    // can not be told anyway.
+    fn file(fd: RawFd) -> CommonResult<Option<Self>> {
+        let stat = stat::fstat(fd)?;
+        if SFlag::from_bits_truncate(stat.st_mode) & SFlag::S_IFMT != SFlag::S_IFREG {
+            return Ok(None);
+        }
+
+        let file = unsafe { File::from_raw_fd(dup_cloexec(fd)?) };
+        let file = OutputFile::Fd(file, stat.st_size as u64);
+        Ok(Some(OutputSide::File(Arc::new(file))))
+    }
+}
+
+// Checks the growth of output files from time to time, as there is no way to be told about it.
+// Closing the write end of the cancel pipe stops it.
+#[derive(Debug)]
+struct FileWatcher {
+    cancel: Option<OwnedFd>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl FileWatcher {
+    fn spawn<F>(files: Vec<(Arc<OutputFile>, u64)>, on_limit_exceeded: F) -> CommonResult<Self>
+    where
+        F: Fn() + Send + 'static,
+    {
+        const CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+        let (cancel_read, cancel_write) = make_pipe()?;
+        let (cancel_read, cancel_write) = unsafe {
+            (
+                OwnedFd::from_raw_fd(cancel_read),
+                OwnedFd::from_raw_fd(cancel_write),
+            )
+        };
+
+        let handle = std::thread::spawn(move || loop {
+            if files.iter().any(|(file, limit)| file.written() > *limit) {
+                on_limit_exceeded();
+                return;
+            }
+
+            let mut fds = [PollFd::new(cancel_read.as_raw_fd(), PollFlags::POLLIN)];
+            match poll::poll(&mut fds, CHECK_INTERVAL.as_millis() as libc::c_int) {
+                Ok(0) | Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => {}
+                _ => return,
+            }
+        });
+
+        Ok(Self {
+            cancel: Some(cancel_write),
+            handle: Some(handle),
+        })
+    }
+
+    fn cancel(&mut self) {
+        self.cancel.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for FileWatcher {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
+
+// Standard IO of the container, the child ends are inherited by the container and put in place
+// of its standard IO, while the parent keeps the other ends of pipes.
+#[derive(Debug, Default)]
+pub struct StdioPipes {
+    pub child: [Option<RawFd>; 3],
+    stdin: Option<(Input, File)>,
+    outputs: [Option<OutputSide>; 2],
+}
+
+impl StdioPipes {
+    pub fn new(stdin: Input, stdout: Output, stderr: Output) -> CommonResult<Self> {
+        let mut res: Self = Default::default();
+        res.child[0] = match stdin {
+            Input::Inherit => None,
+            Input::Fd(fd) => Some(into_cloexec(fd)?),
+            Input::RawFd(fd) => Some(dup_cloexec(fd)?),
+            Input::HostPath(path) => Some(
+                OpenOptions::new()
+                    .read(true)
+                    .custom_flags(libc::O_CLOEXEC)
+                    .open(path)?
+                    .into_raw_fd(),
+            ),
+            stdin => {
+                let (read, write) = make_pipe()?;
+                res.stdin = Some((stdin, unsafe { File::from_raw_fd(write) }));
+                Some(read)
+            }
+        };
+
+        for (i, output) in vec![stdout, stderr].into_iter().enumerate() {
+            let child = match output {
+                Output::Inherit => None,
+                Output::Fd(fd) => Some(into_cloexec(fd)?),
+                Output::RawFd(fd) => Some(dup_cloexec(fd)?),
+                Output::HostPath(path) => Some(
+                    OpenOptions::new()
+                        .write(true)
+                        .create(true)
+                        .truncate(true)
+                        .mode(0o644)
+                        .custom_flags(libc::O_CLOEXEC)
+                        .open(path)?
+                        .into_raw_fd(),
+                ),
+                output => {
+                    let (read, write) = make_pipe()?;
+                    res.outputs[i] =
+                        Some(OutputSide::Pipe(output, unsafe { File::from_raw_fd(read) }));
+                    res.child[i + 1] = Some(write);
+                    continue;
+                }
+            };
+
+            if let Some(fd) = child {
+                res.outputs[i] = OutputSide::file(fd)?;
+            }
+            res.child[i + 1] = child;
+        }
+        Ok(res)
+    }
+
+    // The container opens `path` in place of the output stream `index` (0 for stdout, 1 for
+    // stderr) by itself, after which it is watched instead of the original one.
+    pub fn redirect_output(&mut self, index: usize, path: PathBuf) {
+        self.outputs[index] = Some(OutputSide::File(Arc::new(OutputFile::Path(path))));
+    }
+
+    pub fn close_child_ends(&mut self) -> VoidResult {
+        for fd in self.child.iter_mut() {
+            if let Some(fd) = fd.take() {
+                unistd::close(fd)?;
+            }
+        }
+        Ok(())
+    }
+
+    // Replaces the data pumped through the parent ends, streams set to `Inherit` are left alone.
+    pub fn replace(&mut self, stdin: Input, stdout: Output, stderr: Output) -> VoidResult {
+        match (stdin, &mut self.stdin) {
+            (Input::Inherit, _) => {}
+            (x @ Input::Bytes(_), Some((origin, _)))
+            | (x @ Input::Reader(_), Some((origin, _))) => {
+                *origin = x;
+            }
+            _ => return Err(box super::error::Error::StdioKindChanged),
+        }
+
+        let outputs = vec![stdout, stderr];
+        for (output, side) in outputs.into_iter().zip(self.outputs.iter_mut()) {
+            match (output, side) {
+                (Output::Inherit, _) => {}
+                (x @ Output::Capture(_), Some(OutputSide::Pipe(origin, _)))
+                | (x @ Output::Writer(_), Some(OutputSide::Pipe(origin, _))) => {
+                    *origin = x;
+                }
+                _ => return Err(box super::error::Error::StdioKindChanged),
+            }
+        }
+        Ok(())
+    }
+
+    // Starts pumping data through the parent ends, `on_limit_exceeded` will be called once
+    // an output stream goes beyond its limit. Output files are checked every few milliseconds,
+    // so they may grow somewhat beyond it before that.
+    pub fn spawn<F>(
+        self,
+        stdout_limit: Option<u64>,
+        stderr_limit: Option<u64>,
+        on_limit_exceeded: F,
+    ) -> CommonResult<StdioHandles>
+    where
+        F: Fn() + Send + Clone + 'static,
+    {
+        let stdin_handle = self
+            .stdin
+            .map(|(stdin, pipe)| std::thread::spawn(move || feed_input(stdin, pipe)));
+
+        let spawn_output = |side: Option<OutputSide>, limit: Option<u64>| {
+            let on_limit_exceeded = on_limit_exceeded.clone();
+            side.map(|side| match side {
+                OutputSide::Pipe(output, pipe) => {
+                    OutputHandle::Pipe(std::thread::spawn(move || {
+                        let res = collect_output(output, limit, pipe);
+                        if res.limit_exceeded {
+                            on_limit_exceeded();
+                        }
+                        res
+                    }))
+                }
+                OutputSide::File(file) => OutputHandle::File(file, limit),
+            })
+        };
+
+        let [stdout, stderr] = self.outputs;
+        let stdout = spawn_output(stdout, stdout_limit);
+        let stderr = spawn_output(stderr, stderr_limit);
+
+        let watched: Vec<(Arc<OutputFile>, u64)> = [&stdout, &stderr]
+            .iter()
+            .filter_map(|x| match x {
+                Some(OutputHandle::File(file, Some(limit))) => Some((file.clone(), *limit)),
+                _ => None,
+            })
+            .collect();
+        let watcher = if watched.is_empty() {
+            None
+        } else {
+            Some(FileWatcher::spawn(watched, on_limit_exceeded)?)
+        };
+
+        Ok(StdioHandles {
+            stdin: stdin_handle,
+            stdout: stdout,
+            stderr: stderr,
+            watcher: watcher,
+        })
+    }
+}
+
+#[allow(unused_must_use)]
+fn feed_input(source: Input, mut pipe: File) {
+    // the container may quit without reading all the input, so write errors are ignored
+    match source {
+        Input::Bytes(x) => {
+            pipe.write_all(&x);
+        }
+        Input::Reader(mut x) => {
+            std::io::copy(&mut x, &mut pipe);
+        }
+        _ => {}
+    }
+}
+
+fn collect_output(target: Output, limit: Option<u64>, mut pipe: File) -> CapturedOutput {
+    let (mut writer, limit): (Option<Box<dyn Write + Send>>, Option<u64>) = match target {
+        Output::Capture(x) => {
+            let x = x as u64;
+            (None, Some(limit.map_or(x, |y| std::cmp::min(x, y))))
+        }
+        Output::Writer(w) => (Some(w), limit),
+        _ => (None, limit),
+    };
+
+    let mut res: CapturedOutput = Default::default();
+    let mut written = 0_u64;
+    let mut buf = [0_u8; 8192];
+    loop {
+        let len = match pipe.read(&mut buf) {
+            Ok(0) => break,
+            Ok(x) => x,
+            Err(ref e) if e.kind() == std::io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        };
+
+        let allowed = match limit {
+            Some(limit) => std::cmp::min(len as u64, limit - written) as usize,
+            None => len,
+        };
+
+        match &mut writer {
+            Some(w) => {
+                if w.write_all(&buf[..allowed]).is_err() {
+                    break;
+                }
+            }
+            None => res.data.extend_from_slice(&buf[..allowed]),
+        }
+        written += allowed as u64;
+
+        if allowed < len {
+            res.limit_exceeded = true;
+            break;
+        }
+    }
+
+    if let Some(w) = &mut writer {
+        let _ = w.flush();
+    }
+    res
+}
+
+#[derive(Debug)]
+enum OutputHandle {
+    Pipe(JoinHandle<CapturedOutput>),
+    File(Arc<OutputFile>, Option<u64>),
+}
+
+impl OutputHandle {
+    fn join(self) -> CapturedOutput {
+        match self {
+            OutputHandle::Pipe(handle) => handle.join().unwrap_or_default(),
+            OutputHandle::File(file, limit) => CapturedOutput {
+                data: Vec::new(),
+                limit_exceeded: limit.map_or(false, |x| file.written() > x),
+            },
+        }
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct StdioHandles {
+    stdin: Option<JoinHandle<()>>,
+    stdout: Option<OutputHandle>,
+    stderr: Option<OutputHandle>,
+    watcher: Option<FileWatcher>,
+}
+
+impl StdioHandles {
+    pub fn join(self) -> (CapturedOutput, CapturedOutput) {
+        fn join_output(handle: Option<OutputHandle>) -> CapturedOutput {
+            handle.map(|x| x.join()).unwrap_or_default()
+        }
+
+        if let Some(mut watcher) = self.watcher {
+            watcher.cancel();
+        }
+        if let Some(stdin) = self.stdin {
+            let _ = stdin.join();
+        }
+        (join_output(self.stdout), join_output(self.stderr))
+    }
+}
@@ -2,19 +2,47 @@ use {
     crate::{
         filesystem::MountNamespacedFs,
         idmap,
-        resource::CGroupLimitPolicy,
+        resource::{self, CGroupLimitPolicy, RlimitPolicy},
         security::{self, ApplySecurityPolicy},
-        VoidResult,
+        CommonResult, VoidResult,
     },
     nix::{
-        sys::signal,
-        unistd::{self, Pid},
+        fcntl::OFlag,
+        sys::{signal, wait::WaitPidFlag},
+        unistd,
+    },
+    std::{
+        os::unix::io::RawFd,
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        time::Duration,
     },
-    std::sync::Arc,
 };
 
+#[cfg(feature = "async")]
+mod asynchronous;
+mod channel;
 mod entry;
 mod error;
+mod exec;
+mod init;
+mod io;
+mod process;
+mod pty;
+mod report;
+mod watchdog;
+
+#[cfg(feature = "async")]
+pub use asynchronous::AsyncContainer;
+use error::AtStage;
+
+pub use error::{Error, SetupError, SetupStage};
+pub use exec::ExecProcess;
+pub use io::{Input, Output, Stdio};
+pub use pty::Pty;
+pub use report::{ExitReport, ExitStatus};
 
 #[derive(Debug)]
 pub struct Config {
@@ -22,15 +50,33 @@ pub struct Config {
     pub working_path: String,
     pub hostname: String,
     pub target_executable: String,
+    pub args: Vec<String>,        // arguments after argv[0]
+    pub environment: Vec<String>, // in the form of "KEY=VALUE"
     pub fs: Vec<Box<dyn MountNamespacedFs>>,
     pub security_policies: Vec<Box<dyn ApplySecurityPolicy>>,
     pub cgroup_limits: Box<CGroupLimitPolicy>,
+    pub rlimits: RlimitPolicy,
     pub inner_uid: u32, // uid inside container
     pub inner_gid: u32, // gid inside container
     pub time_limit: std::time::Duration,
     pub stdin: Option<String>,
     pub stdout: Option<String>,
     pub stderr: Option<String>,
+    // Output limits in bytes, the container is killed once an output goes beyond its limit.
+    // They apply to pipes and regular files, not to other kinds of fds such as terminals. Other
+    // files written by the target are only limited by RLIMIT_FSIZE of `rlimits`.
+    pub stdout_limit: Option<u64>,
+    pub stderr_limit: Option<u64>,
+    // The root directory at `<working_path>/<uid>/target` has been set up by the caller, e.g. with
+    // an image already mounted there. It is then neither recreated nor removed by containers.
+    pub prepared_root: bool,
+    // Runs the target under a tiny built-in init as pid 1, which reaps zombies and forwards
+    // signals. The exit status reported is still the one of the target. Without it, the target is
+    // pid 1 itself, which is not killed by signals it does not handle, such as SIGPIPE.
+    pub builtin_init: bool,
+    // Runs without root: ids are mapped the way allowed to unprivileged users. Cgroups have to be
+    // created under a delegated subtree, see `resource::delegated_parent()`.
+    pub rootless: bool,
 }
 
 impl Default for Config {
@@ -40,35 +86,84 @@ impl Default for Config {
             working_path: "/tmp/ssandbox-rs.workspace/".to_string(),
             hostname: "container".to_string(),
             target_executable: "/bin/sh".into(),
+            args: Vec::new(),
+            environment: Vec::new(),
             fs: Vec::new(),
             security_policies: vec![
                 box (Default::default(): security::CapabilityPolicy),
                 box (Default::default(): security::SeccompPolicy),
             ],
             cgroup_limits: Default::default(),
+            rlimits: Default::default(),
             inner_gid: 0,
             inner_uid: 0,
             time_limit: std::time::Duration::from_secs(1),
             stdin: None,
             stdout: None,
             stderr: None,
+            stdout_limit: None,
+            stderr_limit: None,
+            prepared_root: false,
+            builtin_init: false,
+            rootless: false,
         }
     }
 }
 
-#[derive(Debug, Clone)]
+// Tags errors of setting up cgroups with the controller at fault.
+fn cgroup_setup_error(err: Box<dyn std::error::Error>) -> SetupError {
+    let controller = match err.downcast_ref::<resource::Error>() {
+        Some(resource::Error::ControllerFailed(name, _)) => name.clone(),
+        _ => "cgroup".to_string(),
+    };
+    SetupError::new(SetupStage::CGroup(controller), &*err)
+}
+
+#[derive(Debug)]
 pub struct Container {
     config: Arc<Config>,
-    container_pid: Option<Pid>,
+    process: Option<Arc<process::Process>>,
     already_ended: bool,
+    report: Option<ExitReport>,
+    stdin: Input,
+    stdout: Output,
+    stderr: Output,
+    stdio_handles: Option<io::StdioHandles>,
+    pty_enabled: bool,
+    pty: Option<Pty>,
+    builtin_init: Option<bool>, // overrides `Config::builtin_init`
+    time_limit_exceeded: Arc<AtomicBool>,
+    watchdog: Option<watchdog::Watchdog>,
+    monitor: Option<resource::CGroupMonitor>,
+    command_pipe: Option<RawFd>,
+    report_pipe: Option<RawFd>,
+    init_report_pipe: Option<RawFd>, // where the built-in init sends the status of the target
+    stdio_pipes: Option<io::StdioPipes>,
+    already_launched: bool,
 }
 
 impl std::convert::From<Config> for Container {
     fn from(source: Config) -> Self {
         Self {
             config: Arc::new(source),
-            container_pid: None,
+            process: None,
             already_ended: false,
+            report: None,
+            stdin: Input::Inherit,
+            stdout: Output::Inherit,
+            stderr: Output::Inherit,
+            stdio_handles: None,
+            pty_enabled: false,
+            pty: None,
+            builtin_init: None,
+            time_limit_exceeded: Arc::new(AtomicBool::new(false)),
+            watchdog: None,
+            monitor: None,
+            command_pipe: None,
+            report_pipe: None,
+            init_report_pipe: None,
+            stdio_pipes: None,
+            already_launched: false,
         }
     }
 }
@@ -77,8 +172,24 @@ impl std::convert::From<Arc<Config>> for Container {
     fn from(source: Arc<Config>) -> Self {
         Self {
             config: source,
-            container_pid: None,
+            process: None,
             already_ended: false,
+            report: None,
+            stdin: Input::Inherit,
+            stdout: Output::Inherit,
+            stderr: Output::Inherit,
+            stdio_handles: None,
+            pty_enabled: false,
+            pty: None,
+            builtin_init: None,
+            time_limit_exceeded: Arc::new(AtomicBool::new(false)),
+            watchdog: None,
+            monitor: None,
+            command_pipe: None,
+            report_pipe: None,
+            init_report_pipe: None,
+            stdio_pipes: None,
+            already_launched: false,
         }
     }
 }
@@ -87,20 +198,81 @@ impl Container {
     pub fn new() -> Self {
         Self {
             config: Arc::new(Default::default()),
-            container_pid: None,
+            process: None,
             already_ended: false,
+            report: None,
+            stdin: Input::Inherit,
+            stdout: Output::Inherit,
+            stderr: Output::Inherit,
+            stdio_handles: None,
+            pty_enabled: false,
+            pty: None,
+            builtin_init: None,
+            time_limit_exceeded: Arc::new(AtomicBool::new(false)),
+            watchdog: None,
+            monitor: None,
+            command_pipe: None,
+            report_pipe: None,
+            init_report_pipe: None,
+            stdio_pipes: None,
+            already_launched: false,
         }
     }
 
     pub fn has_started(&self) -> bool {
-        self.container_pid != None
+        self.process.is_some()
     }
 
     pub fn has_ened(&self) -> bool {
         self.already_ended
     }
 
+    pub fn has_launched(&self) -> bool {
+        self.already_launched
+    }
+
+    // Once prepared, IO can still be changed until launched, as long as the kind of each stream
+    // stays the same: data pumped by the parent (bytes, readers, captures and writers) may replace
+    // each other, files and fds may not.
+    pub fn set_stdin(&mut self, value: Input) -> &mut Self {
+        self.stdin = value;
+        self
+    }
+
+    pub fn set_stdout(&mut self, value: Output) -> &mut Self {
+        self.stdout = value;
+        self
+    }
+
+    pub fn set_stderr(&mut self, value: Output) -> &mut Self {
+        self.stderr = value;
+        self
+    }
+
+    // Gives the container a pseudo-terminal as its controlling terminal and standard IO.
+    // Streams set with `set_stdin()` etc. still take precedence over it.
+    pub fn set_pty(&mut self, enabled: bool) -> &mut Self {
+        self.pty_enabled = enabled;
+        self
+    }
+
+    pub fn pty(&self) -> Option<&Pty> {
+        self.pty.as_ref()
+    }
+
+    // Runs the target under the built-in init or not, regardless of `Config::builtin_init`.
+    pub fn set_builtin_init(&mut self, enabled: bool) -> &mut Self {
+        self.builtin_init = Some(enabled);
+        self
+    }
+
     pub fn start(&mut self) -> VoidResult {
+        self.prepare()?;
+        self.launch()
+    }
+
+    // Creates the container and sets it up, leaving only the target to be executed by `launch()`.
+    pub fn prepare(&mut self) -> VoidResult {
         const STACK_SIZE: usize = 2 * 1024 * 1024; // 2048kb
 
         if self.has_started() || self.has_ened() {
@@ -110,13 +282,42 @@ impl Container {
         let mut stack_memory = Vec::new();
         stack_memory.resize(STACK_SIZE, 0);
 
-        let (ready_pipe_read, ready_pipe_write) = nix::unistd::pipe()?;
-        let (report_pipe_read, report_pipe_write) = nix::unistd::pipe()?;
+        // the child closes every other file descriptor, CLOEXEC keeps them out of what the parent
+        // executes meanwhile
+        let (command_pipe_read, command_pipe_write) = unistd::pipe2(OFlag::O_CLOEXEC)?;
+        let (report_pipe_read, report_pipe_write) = unistd::pipe2(OFlag::O_CLOEXEC)?;
+        let init_pipe_set = if self.builtin_init.unwrap_or(self.config.builtin_init) {
+            Some(unistd::pipe2(OFlag::O_CLOEXEC)?)
+        } else {
+            None
+        };
+        let mut stdio_pipes = io::StdioPipes::new(
+            std::mem::take(&mut self.stdin),
+            std::mem::take(&mut self.stdout),
+            std::mem::take(&mut self.stderr),
+        )?;
+        // paths set in config take precedence, the container opens them on its own
+        let paths = [&self.config.stdout, &self.config.stderr];
+        for (i, path) in paths.iter().enumerate() {
+            if let Some(path) = path {
+                stdio_pipes.redirect_output(i, path.into());
+            }
+        }
+        let pty_slave = if self.pty_enabled {
+            let (master, slave) = pty::open()?;
+            self.pty = Some(master);
+            Some(slave)
+        } else {
+            None
+        };
 
         let ic = entry::InternalData {
             config: self.config.clone(),
-            ready_pipe_set: (ready_pipe_read, ready_pipe_write),
+            command_pipe_set: (command_pipe_read, command_pipe_write),
             report_pipe_set: (report_pipe_read, report_pipe_write),
+            init_pipe_set: init_pipe_set,
+            stdio: stdio_pipes.child,
+            pty_slave: pty_slave,
         };
 
         use nix::sched::CloneFlags;
@@ -133,78 +334,267 @@ impl Container {
             Ok(x) => x,
             Err(e) => return Err(box error::Error::ForkFailed(e)),
         };
-        self.container_pid = Some(pid);
+        let process = Arc::new(process::Process::new(pid));
+        self.process = Some(process.clone());
+        self.command_pipe = Some(command_pipe_write);
+        self.report_pipe = Some(report_pipe_read);
+        self.init_report_pipe = init_pipe_set.map(|(read, _)| read);
 
-        unistd::close(ready_pipe_read)?;
+        unistd::close(command_pipe_read)?;
         unistd::close(report_pipe_write)?;
+        if let Some((_, write)) = init_pipe_set {
+            unistd::close(write)?;
+        }
+        stdio_pipes.close_child_ends()?;
+        self.stdio_pipes = Some(stdio_pipes);
+        if let Some(slave) = pty_slave {
+            unistd::close(slave)?;
+        }
 
-        match (|| -> VoidResult {
-            idmap::map_to_root(pid)?;
-            self.config.cgroup_limits.apply(self.config.uid, pid)?;
-            Ok(())
-        })() {
-            Err(x) => {
-                signal::kill(pid, signal::SIGKILL)?;
-                return Err(x);
+        let res = (|| -> Result<(), SetupError> {
+            channel::expect(report_pipe_read, channel::Message::Ready)?;
+            if self.config.rootless {
+                idmap::map_to_root_unprivileged(pid)
+            } else {
+                idmap::map_to_root(pid)
             }
-            _ => {}
+            .at_stage(SetupStage::IdMapping)?;
+            self.config
+                .cgroup_limits
+                .apply(self.config.uid, pid)
+                .map_err(cgroup_setup_error)?;
+            channel::send(command_pipe_write, &channel::Message::Mapped)
+                .at_stage(SetupStage::Synchronize)?;
+            channel::expect(report_pipe_read, channel::Message::SetupDone)
+        })();
+        if let Err(x) = res {
+            self.close_pipes()?;
+            process.signal(signal::SIGKILL)?;
+            return Err(box error::Error::from(x));
         }
 
-        // ready, let's tell child:
-        // The closing of ready_pipe ends the block of read() at childs entry.
-        // So that the real command can be executed via execvp().
-        unistd::close(ready_pipe_write)?;
+        Ok(())
+    }
 
-        // our child maybe now complaining about errors
-        let mut child_status_buf = [0_u8; 1];
-        unistd::read(report_pipe_read, &mut child_status_buf)?;
-        if child_status_buf[0] != 0 {
-            let code = child_status_buf[0];
-            let mut addtional_info_length_buf = [0_u8; std::mem::size_of::<usize>()];
-            unistd::read(report_pipe_read, &mut addtional_info_length_buf)?;
-            let addtional_info_length = usize::from_ne_bytes(addtional_info_length_buf);
+    fn close_pipes(&mut self) -> VoidResult {
+        for fd in [self.command_pipe.take(), self.report_pipe.take()]
+            .iter()
+            .flatten()
+        {
+            unistd::close(*fd)?;
+        }
+        Ok(())
+    }
+
+    // Lets a prepared container execute its target.
+    pub fn launch(&mut self) -> VoidResult {
+        let report_pipe_read = self.launch_begin()?;
+        self.launch_end(report_pipe_read, true)
+    }
+
+    // Releases the child, returns the pipe where it reports whether executing is going well.
+    pub(crate) fn launch_begin(&mut self) -> CommonResult<RawFd> {
+        let process = match &self.process {
+            Some(process) => process.clone(),
+            None => return Err(box error::Error::NotStarted),
+        };
+        if self.has_launched() || self.has_ened() {
+            return Err(box error::Error::AlreadyStarted);
+        }
+        self.monitor = Some(self.config.cgroup_limits.monitor(self.config.uid)?);
+        self.already_launched = true;
 
-            let mut addtional_info_buf = Vec::new();
-            addtional_info_buf.resize(addtional_info_length, 0);
-            unistd::read(report_pipe_read, &mut addtional_info_buf)?;
+        if let Some(mut stdio_pipes) = self.stdio_pipes.take() {
+            stdio_pipes.replace(
+                std::mem::take(&mut self.stdin),
+                std::mem::take(&mut self.stdout),
+                std::mem::take(&mut self.stderr),
+            )?;
+            self.stdio_handles = Some(stdio_pipes.spawn(
+                self.config.stdout_limit,
+                self.config.stderr_limit,
+                move || {
+                    let _ = process.signal(signal::SIGKILL);
+                },
+            )?);
+        }
+
+        // ready, let's tell child to execute the target
+        if let Some(command_pipe_write) = self.command_pipe.take() {
+            let res = channel::send(command_pipe_write, &channel::Message::Launch);
+            unistd::close(command_pipe_write)?;
+            res?;
+        }
 
-            let wrapped_error: error::Error =
-                error::EntryError::new(code, &addtional_info_buf).into();
-            return Err(box wrapped_error);
+        match self.report_pipe.take() {
+            Some(x) => Ok(x),
+            None => Err(box error::Error::NotStarted),
         }
+    }
+
+    // Reads the report of the child, and starts the time limit watchdog if asked to.
+    pub(crate) fn launch_end(&mut self, report_pipe_read: RawFd, watchdog: bool) -> VoidResult {
+        let process = match &self.process {
+            Some(process) => process.clone(),
+            None => return Err(box error::Error::NotStarted),
+        };
 
-        let time_limit = self.config.time_limit.clone();
-        std::thread::spawn(move || {
-            std::thread::sleep(time_limit);
+        // our child maybe now complaining about errors, or the pipe gets closed by execve()
+        let res = channel::expect_exec(report_pipe_read);
+        unistd::close(report_pipe_read)?;
+        res.map_err(error::Error::from)?;
 
-            use nix::sys::wait;
-            match wait::waitpid(pid, Some(wait::WaitPidFlag::WNOHANG)) {
-                Ok(wait::WaitStatus::StillAlive) => {
-                    let _ = signal::kill(pid, signal::SIGKILL);
-                }
-                _ => {}
-            };
-        });
+        if !watchdog {
+            return Ok(());
+        }
 
+        self.watchdog = Some(watchdog::Watchdog::spawn(
+            process,
+            self.config.time_limit,
+            self.time_limit_exceeded.clone(),
+        )?);
         Ok(())
     }
 
-    pub fn wait(&mut self) -> VoidResult {
-        if !self.has_ened() {
-            if let Some(pid) = self.container_pid {
-                nix::sys::wait::waitpid(pid, None)?;
+    pub fn wait(&mut self) -> CommonResult<ExitReport> {
+        loop {
+            if let Some(report) = self.wait_with_flags(None)? {
+                return Ok(report);
             }
-            self.already_ended = true;
         }
-        Ok(())
     }
 
+    // Returns `None` if the container is still running after `timeout`.
+    pub fn wait_timeout(&mut self, timeout: Duration) -> CommonResult<Option<ExitReport>> {
+        if let Some(report) = &self.report {
+            return Ok(Some(report.clone()));
+        }
+
+        match &self.process {
+            Some(process) => process.wait_timeout(timeout, None)?,
+            None => return Err(box error::Error::NotStarted),
+        };
+        self.try_wait()
+    }
+
+    // Returns `None` if the container is still running.
+    pub fn try_wait(&mut self) -> CommonResult<Option<ExitReport>> {
+        self.wait_with_flags(Some(WaitPidFlag::WNOHANG))
+    }
+
+    fn wait_with_flags(&mut self, flags: Option<WaitPidFlag>) -> CommonResult<Option<ExitReport>> {
+        if let Some(report) = &self.report {
+            return Ok(Some(report.clone()));
+        }
+
+        let process = match &self.process {
+            Some(process) => process,
+            None => return Err(box error::Error::NotStarted),
+        };
+
+        let mut status = match process.reap(flags)? {
+            Some(status) => status,
+            None => return Ok(None),
+        };
+        if let Some(fd) = self.init_report_pipe.take() {
+            if let Some(target_status) = init::read_report(fd, process.pid())? {
+                status = target_status;
+            }
+        }
+        let status = match ExitStatus::from_wait_status(status) {
+            Some(status) => status,
+            None => return Ok(None),
+        };
+        self.already_ended = true;
+        if let Some(mut watchdog) = self.watchdog.take() {
+            watchdog.cancel();
+        }
+        let cgroup_events = match self.monitor.take() {
+            Some(mut monitor) => monitor.stop(),
+            None => Default::default(),
+        };
+
+        let (stdout, stderr) = match self.stdio_handles.take() {
+            Some(handles) => handles.join(),
+            None => Default::default(),
+        };
+
+        let report = ExitReport {
+            status: status,
+            time_limit_exceeded: self.time_limit_exceeded.load(Ordering::SeqCst),
+            output_limit_exceeded: stdout.limit_exceeded || stderr.limit_exceeded,
+            stdout: stdout.data,
+            stderr: stderr.data,
+            cgroup_events: cgroup_events,
+            io_usage: self.config.cgroup_limits.io_usage(self.config.uid),
+        };
+        self.report = Some(report.clone());
+        Ok(Some(report))
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) fn process(&self) -> Option<&Arc<process::Process>> {
+        self.process.as_ref()
+    }
+
+    #[cfg(feature = "async")]
+    pub(crate) fn mark_time_limit_exceeded(&self) {
+        self.time_limit_exceeded.store(true, Ordering::SeqCst);
+    }
+
+    // Runs another command in the running container, in its namespaces and cgroup, and under the
+    // same security policies.
+    pub fn exec(
+        &self,
+        executable: &str,
+        args: &[String],
+        environment: &[String],
+        io: Stdio,
+    ) -> CommonResult<ExecProcess> {
+        let process = match &self.process {
+            Some(process) if self.has_launched() && !self.has_ened() => process,
+            _ => return Err(box error::Error::NotStarted),
+        };
+        exec::exec(
+            &self.config,
+            process.pid(),
+            executable,
+            args,
+            environment,
+            io,
+        )
+    }
+
+    // Kills every process of the container, then waits for it. The init is killed first, so that
+    // the container is gone even if the cgroup cannot be walked, e.g. when it is not delegated.
     pub fn terminate(&mut self) -> VoidResult {
-        if !self.has_ened() {
-            if let Some(pid) = self.container_pid {
-                signal::kill(pid, signal::SIGKILL)?;
+        if self.has_started() && !self.has_ened() {
+            if let Some(process) = &self.process {
+                process.signal(signal::SIGKILL)?;
             }
+            let res = self.config.cgroup_limits.kill_all(self.config.uid);
             self.wait()?;
+            res?;
+        }
+        Ok(())
+    }
+
+    // Sends `sig` to the container, and kills it if it is still running after `grace_period`.
+    // Without `builtin_init` the target is pid 1 of its namespace, which ignores every signal it
+    // has no handler for, so that such a target always sits out the whole `grace_period`. The
+    // built-in init forwards `sig` to the target instead, where it takes its default action.
+    pub fn terminate_graceful(
+        &mut self,
+        sig: signal::Signal,
+        grace_period: Duration,
+    ) -> VoidResult {
+        if self.has_started() && !self.has_ened() {
+            if let Some(process) = &self.process {
+                process.signal(sig)?;
+            }
+            if self.wait_timeout(grace_period)?.is_none() {
+                self.terminate()?;
+            }
         }
         Ok(())
     }
@@ -213,9 +603,11 @@ impl Container {
         let uid = self.config.uid;
         self.terminate()?;
         self.config.cgroup_limits.delete(uid)?;
-        std::fs::remove_dir_all(
-            std::path::PathBuf::from(&self.config.working_path).join(format!("{}", uid)),
-        )?;
+        if !self.config.prepared_root {
+            std::fs::remove_dir_all(
+                std::path::PathBuf::from(&self.config.working_path).join(format!("{}", uid)),
+            )?;
+        }
         Ok(())
     }
 
@@ -231,6 +623,7 @@ impl Container {
 impl Drop for Container {
     #[allow(unused_must_use)]
     fn drop(&mut self) {
+        self.close_pipes();
         if self.has_started() {
             self.delete();
         }
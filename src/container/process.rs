@@ -0,0 +1,158 @@
+use {
+    crate::{CommonResult, VoidResult},
+    nix::{
+        errno::Errno,
+        poll::{self, PollFd, PollFlags},
+        sys::{
+            signal::{self, Signal},
+            wait::{self, WaitPidFlag, WaitStatus},
+        },
+        unistd::Pid,
+    },
+    std::{
+        os::unix::io::{AsRawFd, FromRawFd, OwnedFd, RawFd},
+        sync::Mutex,
+        time::{Duration, Instant},
+    },
+};
+
+fn pidfd_open(pid: Pid) -> nix::Result<OwnedFd> {
+    let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid.as_raw(), 0) };
+    Errno::result(fd)?;
+    Ok(unsafe { OwnedFd::from_raw_fd(fd as RawFd) })
+}
+
+fn pidfd_send_signal(pidfd: RawFd, sig: Signal) -> nix::Result<()> {
+    let res = unsafe {
+        libc::syscall(
+            libc::SYS_pidfd_send_signal,
+            pidfd,
+            sig as libc::c_int,
+            std::ptr::null::<libc::siginfo_t>(),
+            0,
+        )
+    };
+    Errno::result(res)?;
+    Ok(())
+}
+
+// Waits for the process to exit without reaping it, so that its pid stays reserved.
+// Returns false if `WNOHANG` is given and it is still running.
+fn wait_exited(pid: Pid, nohang: bool) -> nix::Result<bool> {
+    let mut flags = libc::WEXITED | libc::WNOWAIT;
+    if nohang {
+        flags |= libc::WNOHANG;
+    }
+
+    loop {
+        let mut info: libc::siginfo_t = unsafe { std::mem::zeroed() };
+        let res =
+            unsafe { libc::waitid(libc::P_PID, pid.as_raw() as libc::id_t, &mut info, flags) };
+        match Errno::result(res) {
+            Ok(_) => return Ok(unsafe { info.si_pid() } != 0),
+            Err(nix::Error::Sys(Errno::EINTR)) => continue,
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+// The process of a container.
+//
+// Signals go through a pidfd on kernels supporting it (5.3+), which can never refer to another
+// process. Elsewhere the pid is only signaled while holding the lock on `reaped`, as it can not be
+// reused before we reap it.
+#[derive(Debug)]
+pub(crate) struct Process {
+    pid: Pid,
+    pidfd: Option<OwnedFd>,
+    reaped: Mutex<bool>,
+}
+
+impl Process {
+    // `pid` must be a child of ours which has not been reaped yet.
+    pub fn new(pid: Pid) -> Self {
+        Self {
+            pid: pid,
+            pidfd: pidfd_open(pid).ok(),
+            reaped: Mutex::new(false),
+        }
+    }
+
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    pub fn pidfd(&self) -> Option<RawFd> {
+        self.pidfd.as_ref().map(|fd| fd.as_raw_fd())
+    }
+
+    // Does nothing if the process has already exited.
+    pub fn signal(&self, sig: Signal) -> VoidResult {
+        let res = match &self.pidfd {
+            Some(fd) => pidfd_send_signal(fd.as_raw_fd(), sig),
+            None => {
+                let reaped = self.reaped.lock().unwrap();
+                if *reaped {
+                    return Ok(());
+                }
+                signal::kill(self.pid, sig)
+            }
+        };
+
+        match res {
+            Ok(()) | Err(nix::Error::Sys(Errno::ESRCH)) => Ok(()),
+            Err(e) => Err(e.into()),
+        }
+    }
+
+    // Returns false if neither the process exited nor `cancel` became readable within `timeout`.
+    pub fn wait_timeout(&self, timeout: Duration, cancel: Option<RawFd>) -> CommonResult<bool> {
+        // without a pidfd, we have to check the process from time to time
+        const CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+        let deadline = Instant::now() + timeout;
+        loop {
+            if self.pidfd.is_none() {
+                let reaped = self.reaped.lock().unwrap();
+                if *reaped || wait_exited(self.pid, true)? {
+                    return Ok(true);
+                }
+            }
+
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining == Duration::from_secs(0) {
+                return Ok(false);
+            }
+            let remaining = match &self.pidfd {
+                Some(_) => remaining,
+                None => remaining.min(CHECK_INTERVAL),
+            };
+
+            let mut fds: Vec<PollFd> = self
+                .pidfd()
+                .iter()
+                .chain(cancel.iter())
+                .map(|fd| PollFd::new(*fd, PollFlags::POLLIN))
+                .collect();
+            let remaining = remaining.as_millis().min(libc::c_int::MAX as u128);
+            match poll::poll(&mut fds, remaining as libc::c_int) {
+                Ok(0) | Err(nix::Error::Sys(Errno::EINTR)) => {}
+                Ok(_) => return Ok(true),
+                Err(e) => return Err(e.into()),
+            }
+        }
+    }
+
+    // Returns `None` if `WNOHANG` is given and the process is still running.
+    pub fn reap(&self, flags: Option<WaitPidFlag>) -> CommonResult<Option<WaitStatus>> {
+        let nohang = flags.map_or(false, |x| x.contains(WaitPidFlag::WNOHANG));
+        if !wait_exited(self.pid, nohang)? {
+            return Ok(None);
+        }
+
+        let mut reaped = self.reaped.lock().unwrap();
+        let status = wait::waitpid(self.pid, flags)?;
+        *reaped = true;
+        Ok(Some(status))
+    }
+}
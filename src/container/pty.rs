@@ -0,0 +1,196 @@
+use {
+    crate::{CommonResult, VoidResult},
+    nix::{
+        fcntl::{self, FcntlArg, FdFlag},
+        poll::{self, PollFd, PollFlags},
+        pty::{self, Winsize},
+        sys::{
+            signal::{self, SigAction, SigHandler, SigSet, Signal},
+            termios::{self, SetArg, Termios},
+        },
+        unistd,
+    },
+    std::{
+        fs::File,
+        io::{Read, Write},
+        os::unix::io::{AsRawFd, FromRawFd, RawFd},
+        sync::atomic::{AtomicBool, Ordering},
+    },
+};
+
+const STDIN_FN: RawFd = 0;
+
+fn get_window_size(fd: RawFd) -> Option<Winsize> {
+    let mut size: Winsize = unsafe { std::mem::zeroed() };
+    match unsafe { libc::ioctl(fd, libc::TIOCGWINSZ, &mut size) } {
+        0 => Some(size),
+        _ => None,
+    }
+}
+
+fn set_window_size(fd: RawFd, size: &Winsize) -> VoidResult {
+    nix::errno::Errno::result(unsafe { libc::ioctl(fd, libc::TIOCSWINSZ, size) })?;
+    Ok(())
+}
+
+// Allocates a pty pair, sized after the terminal of the caller if there is one.
+// Returns the master side and the raw fd of the slave side, which the container inherits.
+pub(crate) fn open() -> CommonResult<(Pty, RawFd)> {
+    let size = get_window_size(STDIN_FN);
+    let res = pty::openpty(size.as_ref(), None)?;
+    for fd in [res.master, res.slave].iter() {
+        fcntl::fcntl(*fd, FcntlArg::F_SETFD(FdFlag::FD_CLOEXEC))?;
+    }
+
+    let master = unsafe { File::from_raw_fd(res.master) };
+    Ok((Pty { master: master }, res.slave))
+}
+
+// Called inside the container, makes the slave side its controlling terminal and standard IO.
+pub(crate) fn attach(slave: RawFd) -> VoidResult {
+    unistd::setsid()?;
+    nix::errno::Errno::result(unsafe { libc::ioctl(slave, libc::TIOCSCTTY as _, 0) })?;
+    for fd in 0..3 {
+        unistd::dup2(slave, fd)?;
+    }
+    unistd::close(slave)?;
+    Ok(())
+}
+
+static WINDOW_RESIZED: AtomicBool = AtomicBool::new(false);
+
+extern "C" fn handle_sigwinch(_: libc::c_int) {
+    WINDOW_RESIZED.store(true, Ordering::SeqCst);
+}
+
+// Puts the terminal of the caller into raw mode, and restores it when dropped.
+struct RawModeGuard {
+    origin: Termios,
+}
+
+impl RawModeGuard {
+    fn new(fd: RawFd) -> CommonResult<Self> {
+        let origin = termios::tcgetattr(fd)?;
+        let mut raw = origin.clone();
+        termios::cfmakeraw(&mut raw);
+        termios::tcsetattr(fd, SetArg::TCSANOW, &raw)?;
+        Ok(Self { origin: origin })
+    }
+}
+
+impl Drop for RawModeGuard {
+    #[allow(unused_must_use)]
+    fn drop(&mut self) {
+        termios::tcsetattr(STDIN_FN, SetArg::TCSANOW, &self.origin);
+    }
+}
+
+// Master side of the pseudo-terminal of a container.
+#[derive(Debug)]
+pub struct Pty {
+    master: File,
+}
+
+impl Pty {
+    pub fn resize(&self, rows: u16, cols: u16) -> VoidResult {
+        let size = Winsize {
+            ws_row: rows,
+            ws_col: cols,
+            ws_xpixel: 0,
+            ws_ypixel: 0,
+        };
+        set_window_size(self.master.as_raw_fd(), &size)
+    }
+
+    pub fn try_clone(&self) -> CommonResult<Self> {
+        Ok(Self {
+            master: self.master.try_clone()?,
+        })
+    }
+
+    // Connects the pty to the standard IO of the current process until the container closes it,
+    // with the terminal of the caller in raw mode and its window size passed on meanwhile.
+    pub fn proxy_stdio(&self) -> VoidResult {
+        let _guard = if unistd::isatty(STDIN_FN)? {
+            Some(RawModeGuard::new(STDIN_FN)?)
+        } else {
+            None
+        };
+
+        let action = SigAction::new(
+            SigHandler::Handler(handle_sigwinch),
+            signal::SaFlags::empty(),
+            SigSet::empty(),
+        );
+        let origin_action = unsafe { signal::sigaction(Signal::SIGWINCH, &action)? };
+        let res = self.do_proxy();
+        unsafe { signal::sigaction(Signal::SIGWINCH, &origin_action)? };
+        res
+    }
+
+    fn do_proxy(&self) -> VoidResult {
+        let master = self.master.as_raw_fd();
+        let mut stdin_open = true;
+        let mut buf = [0_u8; 4096];
+        loop {
+            if WINDOW_RESIZED.swap(false, Ordering::SeqCst) {
+                if let Some(size) = get_window_size(STDIN_FN) {
+                    set_window_size(master, &size)?;
+                }
+            }
+
+            let mut fds = vec![PollFd::new(master, PollFlags::POLLIN)];
+            if stdin_open {
+                fds.push(PollFd::new(STDIN_FN, PollFlags::POLLIN));
+            }
+            match poll::poll(&mut fds, -1) {
+                Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => continue,
+                Err(e) => return Err(e.into()),
+                Ok(_) => {}
+            }
+
+            if stdin_open && fds[1].revents().map_or(false, |x| !x.is_empty()) {
+                match unistd::read(STDIN_FN, &mut buf)? {
+                    0 => stdin_open = false,
+                    len => (&self.master).write_all(&buf[..len])?,
+                }
+            }
+
+            if fds[0].revents().map_or(false, |x| !x.is_empty()) {
+                // reading the master side fails with EIO once every slave side has been closed
+                match unistd::read(master, &mut buf) {
+                    Ok(0) | Err(nix::Error::Sys(nix::errno::Errno::EIO)) => return Ok(()),
+                    Ok(len) => {
+                        let mut stdout = std::io::stdout();
+                        stdout.write_all(&buf[..len])?;
+                        stdout.flush()?;
+                    }
+                    Err(nix::Error::Sys(nix::errno::Errno::EINTR)) => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+        }
+    }
+}
+
+impl Read for Pty {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        self.master.read(buf)
+    }
+}
+
+impl Write for Pty {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.master.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.master.flush()
+    }
+}
+
+impl AsRawFd for Pty {
+    fn as_raw_fd(&self) -> RawFd {
+        self.master.as_raw_fd()
+    }
+}
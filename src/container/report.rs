@@ -0,0 +1,35 @@
+use {
+    crate::resource::{CGroupEvents, IoUsage},
+    nix::sys::{signal::Signal, wait::WaitStatus},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExitStatus {
+    Exited(i32),
+    Signaled(Signal),
+}
+
+impl ExitStatus {
+    pub fn success(&self) -> bool {
+        *self == ExitStatus::Exited(0)
+    }
+
+    pub(crate) fn from_wait_status(status: WaitStatus) -> Option<Self> {
+        match status {
+            WaitStatus::Exited(_, code) => Some(ExitStatus::Exited(code)),
+            WaitStatus::Signaled(_, signal, _) => Some(ExitStatus::Signaled(signal)),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ExitReport {
+    pub status: ExitStatus,
+    pub time_limit_exceeded: bool,
+    pub output_limit_exceeded: bool,
+    pub stdout: Vec<u8>, // captured output, empty unless `Output::Capture` is used
+    pub stderr: Vec<u8>,
+    pub cgroup_events: CGroupEvents, // OOM kills and refused forks in the cgroup of the container
+    pub io_usage: IoUsage,
+}
@@ -0,0 +1,64 @@
+use {
+    super::process::Process,
+    crate::CommonResult,
+    nix::{fcntl::OFlag, sys::signal, unistd},
+    std::{
+        os::unix::io::{AsRawFd, FromRawFd, OwnedFd},
+        sync::{
+            atomic::{AtomicBool, Ordering},
+            Arc,
+        },
+        thread::JoinHandle,
+        time::Duration,
+    },
+};
+
+// Kills the container once it runs out of time.
+// Closing the write end of the cancel pipe wakes it up early, so that it never outlives a wait.
+#[derive(Debug)]
+pub(crate) struct Watchdog {
+    cancel: Option<OwnedFd>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Watchdog {
+    pub fn spawn(
+        process: Arc<Process>,
+        time_limit: Duration,
+        exceeded: Arc<AtomicBool>,
+    ) -> CommonResult<Self> {
+        let (cancel_read, cancel_write) = unistd::pipe2(OFlag::O_CLOEXEC)?;
+        let (cancel_read, cancel_write) = unsafe {
+            (
+                OwnedFd::from_raw_fd(cancel_read),
+                OwnedFd::from_raw_fd(cancel_write),
+            )
+        };
+
+        let handle = std::thread::spawn(move || {
+            if let Ok(false) = process.wait_timeout(time_limit, Some(cancel_read.as_raw_fd())) {
+                exceeded.store(true, Ordering::SeqCst);
+                let _ = process.signal(signal::SIGKILL);
+            }
+        });
+
+        Ok(Self {
+            cancel: Some(cancel_write),
+            handle: Some(handle),
+        })
+    }
+
+    // Stops the watchdog, once this returns it will not touch the container any more.
+    pub fn cancel(&mut self) {
+        self.cancel.take();
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+impl Drop for Watchdog {
+    fn drop(&mut self) {
+        self.cancel();
+    }
+}
@@ -1,6 +1,6 @@
 use nix::mount::{self, MsFlags};
 
-pub trait MountNamespacedFs: std::fmt::Debug {
+pub trait MountNamespacedFs: std::fmt::Debug + Send + Sync {
     fn loading(
         &self,
         _base_path: &std::path::Path,
@@ -12,6 +12,11 @@ pub trait MountNamespacedFs: std::fmt::Debug {
     fn loaded(&self) -> Result<(), Box<dyn std::error::Error>> {
         Ok(())
     }
+
+    // Where it is mounted inside the container, only used to describe errors.
+    fn target(&self) -> String {
+        String::new()
+    }
 }
 
 #[derive(Debug)]
@@ -22,6 +27,10 @@ impl MountNamespacedFs for MountTmpFs {
         mount::mount::<_, _, _, str>(Some("tmpfs"), "/tmp", Some("tmpfs"), MsFlags::empty(), None)?;
         Ok(())
     }
+
+    fn target(&self) -> String {
+        "/tmp".to_string()
+    }
 }
 
 #[derive(Debug)]
@@ -32,6 +41,10 @@ impl MountNamespacedFs for MountProcFs {
         mount::mount::<_, _, _, str>(Some("proc"), "/proc", Some("proc"), MsFlags::empty(), None)?;
         Ok(())
     }
+
+    fn target(&self) -> String {
+        "/proc".to_string()
+    }
 }
 
 #[derive(Debug)]
@@ -60,6 +73,10 @@ impl MountNamespacedFs for MountBindFs {
         )?;
         Ok(())
     }
+
+    fn target(&self) -> String {
+        "/".to_string()
+    }
 }
 
 #[derive(Debug)]
@@ -96,6 +113,10 @@ impl MountNamespacedFs for MountReadOnlyBindFs {
         )?;
         Ok(())
     }
+
+    fn target(&self) -> String {
+        "/".to_string()
+    }
 }
 
 #[derive(Debug)]
@@ -163,6 +184,63 @@ impl MountNamespacedFs for MountExtraFs {
         )?;
         Ok(())
     }
+
+    fn target(&self) -> String {
+        format!("/{}", self.inner)
+    }
+}
+
+#[derive(Debug)]
+pub struct MountReadOnlyExtraFs {
+    source: String,
+    inner: String,
+}
+
+impl MountReadOnlyExtraFs {
+    pub fn build(source: String, inner: String) -> Self {
+        let inner = std::path::PathBuf::from(&inner);
+        let inner = inner
+            .strip_prefix("/")
+            .unwrap_or(&inner)
+            .to_string_lossy()
+            .to_owned()
+            .to_string();
+
+        Self {
+            source: source,
+            inner: inner,
+        }
+    }
+}
+
+impl MountNamespacedFs for MountReadOnlyExtraFs {
+    fn loading(
+        &self,
+        base_path: &std::path::Path,
+        _: &std::path::Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let target = base_path.join(&self.inner);
+        mount::mount::<str, _, str, str>(
+            Some(&self.source),
+            &target,
+            None,
+            MsFlags::MS_REC | MsFlags::MS_BIND,
+            None,
+        )?;
+
+        mount::mount::<str, _, str, str>(
+            None,
+            &target,
+            None,
+            MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY | MsFlags::MS_REC,
+            None,
+        )?;
+        Ok(())
+    }
+
+    fn target(&self) -> String {
+        format!("/{}", self.inner)
+    }
 }
 
 #[derive(Debug)]
@@ -215,4 +293,89 @@ impl MountNamespacedFs for MountSizedTmpFs {
         )?;
         Ok(())
     }
+
+    fn target(&self) -> String {
+        self.target.clone()
+    }
+}
+
+fn bind_read_only(
+    source: &std::path::Path,
+    target: &std::path::Path,
+) -> Result<(), Box<dyn std::error::Error>> {
+    mount::mount::<_, _, str, str>(Some(source), target, None, MsFlags::MS_BIND, None)?;
+    mount::mount::<str, _, str, str>(
+        None,
+        target,
+        None,
+        MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY,
+        None,
+    )?;
+    Ok(())
+}
+
+// Makes single host files visible read-only inside the container, under one directory.
+// The directory itself is created in the workspace, so only `inner` has to exist in the image.
+#[derive(Debug)]
+pub struct MountReadOnlyFilesFs {
+    files: Vec<(String, String)>,
+    inner: String,
+}
+
+impl MountReadOnlyFilesFs {
+    pub fn new(inner: String) -> Self {
+        let inner = std::path::PathBuf::from(&inner);
+        let inner = inner
+            .strip_prefix("/")
+            .unwrap_or(&inner)
+            .to_string_lossy()
+            .to_owned()
+            .to_string();
+
+        Self {
+            files: Vec::new(),
+            inner: inner,
+        }
+    }
+
+    pub fn add_file(&mut self, name: String, source: String) -> &mut Self {
+        self.files.push((name, source));
+        self
+    }
+}
+
+impl MountNamespacedFs for MountReadOnlyFilesFs {
+    fn loading(
+        &self,
+        base_path: &std::path::Path,
+        work_path: &std::path::Path,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let staging = work_path.join("files").join(&self.inner);
+        std::fs::create_dir_all(&staging)?;
+        for (name, source) in self.files.iter() {
+            let target = staging.join(name);
+            std::fs::File::create(&target)?;
+            bind_read_only(std::path::Path::new(source), &target)?;
+        }
+
+        mount::mount::<std::path::Path, _, str, str>(
+            Some(&staging),
+            &base_path.join(&self.inner),
+            None,
+            MsFlags::MS_REC | MsFlags::MS_BIND,
+            None,
+        )?;
+        mount::mount::<str, _, str, str>(
+            None,
+            &base_path.join(&self.inner),
+            None,
+            MsFlags::MS_BIND | MsFlags::MS_REMOUNT | MsFlags::MS_RDONLY | MsFlags::MS_REC,
+            None,
+        )?;
+        Ok(())
+    }
+
+    fn target(&self) -> String {
+        format!("/{}", self.inner)
+    }
 }
@@ -19,4 +19,19 @@ pub fn map_to_root(pid: unistd::Pid) -> VoidResult {
     map_uid_to_root(pid)?;
     map_gid_to_root(pid)?;
     Ok(())
+}
+
+// Without CAP_SETGID in the parent namespace, setgroups(2) has to be disabled in the child
+// namespace before its gid_map can be written.
+pub fn deny_setgroups(pid: unistd::Pid) -> VoidResult {
+    fs::write(format!("/proc/{}/setgroups", pid), "deny")?;
+    Ok(())
+}
+
+// Maps the same way as `map_to_root()`, but works without root.
+pub fn map_to_root_unprivileged(pid: unistd::Pid) -> VoidResult {
+    map_uid_to_root(pid)?;
+    deny_setgroups(pid)?;
+    map_gid_to_root(pid)?;
+    Ok(())
 }
\ No newline at end of file
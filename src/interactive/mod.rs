@@ -0,0 +1,148 @@
+use {
+    crate::{
+        container::{Container, ExitReport, ExitStatus, Input, Output},
+        CommonResult,
+    },
+    nix::{fcntl::OFlag, sys::signal::Signal, unistd},
+    std::os::unix::io::{FromRawFd, OwnedFd},
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Party {
+    Contestant,
+    Interactor,
+}
+
+#[derive(Debug, Clone)]
+pub struct InteractiveReport {
+    pub contestant: ExitReport,
+    pub interactor: ExitReport,
+}
+
+impl InteractiveReport {
+    // The party whose report decides the result.
+    // The interactor has the final say, unless the contestant failed on its own: dying of
+    // SIGPIPE only means the interactor has stopped reading, which is the interactor's verdict.
+    // `run()` makes sure SIGPIPE does kill the contestant, instead of leaving it to handle EPIPE.
+    pub fn decisive(&self) -> Party {
+        match self.contestant.status {
+            ExitStatus::Signaled(Signal::SIGPIPE) => Party::Interactor,
+            status if !status.success() => Party::Contestant,
+            _ => Party::Interactor,
+        }
+    }
+
+    pub fn decisive_report(&self) -> &ExitReport {
+        match self.decisive() {
+            Party::Contestant => &self.contestant,
+            Party::Interactor => &self.interactor,
+        }
+    }
+}
+
+fn make_pipe() -> CommonResult<(OwnedFd, OwnedFd)> {
+    let (read, write) = unistd::pipe2(OFlag::O_CLOEXEC)?;
+    Ok(unsafe { (OwnedFd::from_raw_fd(read), OwnedFd::from_raw_fd(write)) })
+}
+
+// Runs both containers with the standard output of each connected to the standard input of the
+// other one, and waits for both of them. Their stdin and stdout settings are overwritten, and both
+// run under the built-in init, as pid 1 of a namespace is not killed by SIGPIPE.
+pub fn run(
+    contestant: &mut Container,
+    interactor: &mut Container,
+) -> CommonResult<InteractiveReport> {
+    let (to_interactor_read, to_interactor_write) = make_pipe()?;
+    let (to_contestant_read, to_contestant_write) = make_pipe()?;
+
+    interactor
+        .set_stdin(Input::Fd(to_interactor_read))
+        .set_stdout(Output::Fd(to_contestant_write))
+        .set_builtin_init(true);
+    contestant
+        .set_stdin(Input::Fd(to_contestant_read))
+        .set_stdout(Output::Fd(to_interactor_write))
+        .set_builtin_init(true);
+
+    interactor.start()?;
+    if let Err(e) = contestant.start() {
+        interactor.terminate()?;
+        return Err(e);
+    }
+
+    let interactor_report = interactor.wait();
+    let contestant_report = contestant.wait()?;
+    Ok(InteractiveReport {
+        contestant: contestant_report,
+        interactor: interactor_report?,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use {
+        super::*,
+        crate::{container::Config, filesystem::MountReadOnlyBindFs},
+    };
+
+    fn report(status: ExitStatus) -> ExitReport {
+        ExitReport {
+            status: status,
+            time_limit_exceeded: false,
+            output_limit_exceeded: false,
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            cgroup_events: Default::default(),
+            io_usage: Default::default(),
+        }
+    }
+
+    fn decisive(contestant: ExitStatus) -> Party {
+        InteractiveReport {
+            contestant: report(contestant),
+            interactor: report(ExitStatus::Exited(1)),
+        }
+        .decisive()
+    }
+
+    #[test]
+    fn decisive_party() {
+        assert_eq!(decisive(ExitStatus::Exited(0)), Party::Interactor);
+        assert_eq!(
+            decisive(ExitStatus::Signaled(Signal::SIGPIPE)),
+            Party::Interactor
+        );
+        assert_eq!(decisive(ExitStatus::Exited(1)), Party::Contestant);
+        assert_eq!(
+            decisive(ExitStatus::Signaled(Signal::SIGSEGV)),
+            Party::Contestant
+        );
+    }
+
+    // Runs `script` with the shell of the host, which is mounted read-only as the root.
+    fn shell(script: &str) -> Container {
+        let mut config: Config = Default::default();
+        config.args = vec!["-c".to_string(), script.to_string()];
+        config
+            .fs
+            .push(Box::new(MountReadOnlyBindFs::from("/".to_string())));
+        config.time_limit = std::time::Duration::from_secs(5);
+        Container::from(config)
+    }
+
+    #[test]
+    #[ignore] // needs root
+    fn interactor_exits_early() {
+        // the contestant would keep writing forever, unless killed by SIGPIPE
+        let mut contestant = shell("while :; do echo 1; done");
+        let mut interactor = shell("read x; exit 3");
+        let report = run(&mut contestant, &mut interactor).unwrap();
+        assert_eq!(
+            report.contestant.status,
+            ExitStatus::Signaled(Signal::SIGPIPE)
+        );
+        assert!(!report.contestant.time_limit_exceeded);
+        assert_eq!(report.decisive(), Party::Interactor);
+        assert_eq!(report.decisive_report().status, ExitStatus::Exited(3));
+    }
+}
@@ -0,0 +1,182 @@
+use {
+    crate::{
+        container::{Config, Container, ExitReport, Input, Output},
+        filesystem::MountReadOnlyBindFs,
+        CommonResult, VoidResult,
+    },
+    std::{fs, path::PathBuf, sync::Arc},
+};
+
+const OUTPUT_LIMIT: usize = 64 * 1024 * 1024; // 64Mb
+
+#[derive(Debug, Clone)]
+pub struct TestCase {
+    pub input: Vec<u8>,
+    pub expected: Vec<u8>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaseVerdict {
+    Accepted,
+    WrongAnswer,
+    TimeLimitExceeded,
+    MemoryLimitExceeded,
+    OutputLimitExceeded,
+    RuntimeError,
+}
+
+#[derive(Debug, Clone)]
+pub struct CaseReport {
+    pub verdict: CaseVerdict,
+    pub report: ExitReport,
+}
+
+// Compares outputs ignoring trailing whitespace on each line and trailing empty lines.
+pub fn compare_lines(output: &[u8], expected: &[u8]) -> bool {
+    fn lines(x: &[u8]) -> Vec<&[u8]> {
+        let mut res: Vec<&[u8]> = x
+            .split(|c| *c == b'\n')
+            .map(|line| {
+                let len = line
+                    .iter()
+                    .rposition(|c| !c.is_ascii_whitespace())
+                    .map_or(0, |x| x + 1);
+                &line[..len]
+            })
+            .collect();
+        while res.last().map_or(false, |x| x.is_empty()) {
+            res.pop();
+        }
+        res
+    }
+
+    lines(output) == lines(expected)
+}
+
+// The root directory of the containers, created once for every test case. The image is bound
+// onto it in the mount namespace of each container, not in the one of the host, so that nothing
+// is left mounted if this process dies, and so that it works without root.
+#[derive(Debug)]
+struct PreparedRoot {
+    workspace: PathBuf,
+    released: bool,
+}
+
+impl PreparedRoot {
+    fn new(config: &Config) -> CommonResult<Self> {
+        let workspace = PathBuf::from(&config.working_path).join(config.uid.to_string());
+        fs::create_dir_all(workspace.join("target"))?;
+        Ok(Self {
+            workspace: workspace,
+            released: false,
+        })
+    }
+
+    fn release(&mut self) -> VoidResult {
+        self.released = true;
+        fs::remove_dir_all(&self.workspace)?;
+        Ok(())
+    }
+}
+
+impl Drop for PreparedRoot {
+    #[allow(unused_must_use)]
+    fn drop(&mut self) {
+        if !self.released {
+            self.release();
+        }
+    }
+}
+
+// Runs one program against many test cases, each one in a fresh container.
+// The image is mounted read-only at the root by the runner, `config` must not mount it again.
+pub struct BatchRunner {
+    config: Arc<Config>,
+    image: String,
+    stop_on_failure: bool,
+    comparator: Box<dyn Fn(&[u8], &[u8]) -> bool>,
+}
+
+impl std::fmt::Debug for BatchRunner {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("BatchRunner")
+            .field("config", &self.config)
+            .field("image", &self.image)
+            .field("stop_on_failure", &self.stop_on_failure)
+            .finish()
+    }
+}
+
+impl BatchRunner {
+    pub fn new(mut config: Config, image: String) -> Self {
+        config.prepared_root = true;
+        // before anything mounted inside of it
+        config
+            .fs
+            .insert(0, Box::new(MountReadOnlyBindFs::from(image.clone())));
+        Self {
+            config: Arc::new(config),
+            image: image,
+            stop_on_failure: false,
+            comparator: box compare_lines,
+        }
+    }
+
+    pub fn set_stop_on_failure(&mut self, value: bool) -> &mut Self {
+        self.stop_on_failure = value;
+        self
+    }
+
+    pub fn set_comparator<F>(&mut self, value: F) -> &mut Self
+    where
+        F: Fn(&[u8], &[u8]) -> bool + 'static,
+    {
+        self.comparator = box value;
+        self
+    }
+
+    fn run_case(&self, case: &TestCase) -> CommonResult<CaseReport> {
+        let mut container = Container::from(self.config.clone());
+        container
+            .set_stdin(Input::Bytes(case.input.clone()))
+            .set_stdout(Output::Capture(OUTPUT_LIMIT))
+            .set_stderr(Output::Writer(box std::io::sink()));
+        container.start()?;
+        let report = container.wait()?;
+        container.delete()?;
+
+        let verdict = if report.time_limit_exceeded {
+            CaseVerdict::TimeLimitExceeded
+        } else if report.cgroup_events.memory_limit_exceeded() {
+            CaseVerdict::MemoryLimitExceeded
+        } else if report.output_limit_exceeded {
+            CaseVerdict::OutputLimitExceeded
+        } else if !report.status.success() {
+            CaseVerdict::RuntimeError
+        } else if (self.comparator)(&report.stdout, &case.expected) {
+            CaseVerdict::Accepted
+        } else {
+            CaseVerdict::WrongAnswer
+        };
+
+        Ok(CaseReport {
+            verdict: verdict,
+            report: report,
+        })
+    }
+
+    pub fn run(&self, cases: &[TestCase]) -> CommonResult<Vec<CaseReport>> {
+        let mut root = PreparedRoot::new(&self.config)?;
+        let mut res = Vec::new();
+        for case in cases.iter() {
+            let report = self.run_case(case)?;
+            let failed = report.verdict != CaseVerdict::Accepted;
+            res.push(report);
+            if failed && self.stop_on_failure {
+                break;
+            }
+        }
+        root.release()?;
+        Ok(res)
+    }
+}
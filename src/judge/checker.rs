@@ -0,0 +1,102 @@
+use {
+    super::{CheckResult, Verdict},
+    crate::{
+        container::{Config, Container, ExitReport, ExitStatus, Output},
+        filesystem::MountReadOnlyFilesFs,
+        CommonResult,
+    },
+    std::path::Path,
+};
+
+// exit codes of testlib checkers
+const EXIT_OK: i32 = 0;
+const EXIT_WA: i32 = 1;
+const EXIT_PE: i32 = 2;
+const EXIT_FAIL: i32 = 3;
+const EXIT_DIRT: i32 = 4;
+const EXIT_POINTS: i32 = 7;
+const EXIT_UNEXPECTED_EOF: i32 = 8;
+const EXIT_PARTIALLY: i32 = 16;
+
+const MESSAGE_LIMIT: usize = 64 * 1024; // 64kb
+
+// Runs a testlib-style checker inside a container, as `checker <input> <output> <answer>`.
+#[derive(Debug)]
+pub struct Checker {
+    config: Config,
+    mount_point: String,
+}
+
+impl From<Config> for Checker {
+    fn from(config: Config) -> Self {
+        Self {
+            config: config,
+            mount_point: "/mnt".to_string(),
+        }
+    }
+}
+
+impl Checker {
+    // Directory inside the container where the input, output and answer files show up,
+    // it must exist in the image.
+    pub fn set_mount_point(&mut self, value: String) -> &mut Self {
+        self.mount_point = value;
+        self
+    }
+
+    pub fn check(self, input: &Path, output: &Path, answer: &Path) -> CommonResult<CheckResult> {
+        let mut config = self.config;
+        let mut files = MountReadOnlyFilesFs::new(self.mount_point.clone());
+        let mut args = Vec::new();
+        for (name, source) in [("input", input), ("output", output), ("answer", answer)].iter() {
+            files.add_file(name.to_string(), source.to_string_lossy().into_owned());
+            args.push(format!(
+                "{}/{}",
+                self.mount_point.trim_end_matches('/'),
+                name
+            ));
+        }
+        config.fs.push(box files);
+        config.args = args;
+
+        let mut container = Container::from(config);
+        container.set_stderr(Output::Capture(MESSAGE_LIMIT));
+        container.start()?;
+        let report = container.wait()?;
+        Ok(parse_result(report))
+    }
+}
+
+fn parse_points(message: &str) -> Option<(f64, String)> {
+    let rest = message.strip_prefix("points ")?;
+    let mut parts = rest.splitn(2, char::is_whitespace);
+    let points = parts.next()?.parse().ok()?;
+    Some((points, parts.next().unwrap_or("").trim().to_string()))
+}
+
+pub fn parse_result(report: ExitReport) -> CheckResult {
+    let message = String::from_utf8_lossy(&report.stderr).trim().to_string();
+    let (verdict, message) = match report.status {
+        ExitStatus::Exited(EXIT_OK) => (Verdict::Accepted, message),
+        ExitStatus::Exited(EXIT_WA) => (Verdict::WrongAnswer, message),
+        ExitStatus::Exited(EXIT_PE)
+        | ExitStatus::Exited(EXIT_DIRT)
+        | ExitStatus::Exited(EXIT_UNEXPECTED_EOF) => (Verdict::PresentationError, message),
+        ExitStatus::Exited(EXIT_POINTS) => match parse_points(&message) {
+            Some((points, message)) => (Verdict::Points(points), message),
+            None => (Verdict::Fail, message),
+        },
+        ExitStatus::Exited(x) if x >= EXIT_PARTIALLY => {
+            (Verdict::PartiallyCorrect(x - EXIT_PARTIALLY), message)
+        }
+        ExitStatus::Exited(EXIT_FAIL) | ExitStatus::Exited(_) | ExitStatus::Signaled(_) => {
+            (Verdict::Fail, message)
+        }
+    };
+
+    CheckResult {
+        verdict: verdict,
+        message: message,
+        report: report,
+    }
+}
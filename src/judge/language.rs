@@ -0,0 +1,230 @@
+use {crate::security::SeccompPolicy, std::collections::HashMap, std::time::Duration};
+
+// Sources and artifacts live in this directory inside the containers.
+pub const WORK_DIR: &str = "/mnt";
+
+const DEFAULT_PATH: &str = "PATH=/usr/local/sbin:/usr/local/bin:/usr/sbin:/usr/bin:/sbin:/bin";
+
+// Starting threads, denied to programs whose runtime does not need them.
+const THREAD_SYSCALLS: &[&str] = &["clone", "clone3"];
+
+#[derive(Debug, Clone)]
+pub struct Limits {
+    pub time_limit: Duration,
+    pub memory_limit: i64, // in bytes
+    pub fork_limit: u32,   // threads are counted as well
+}
+
+#[derive(Debug, Clone)]
+pub struct Language {
+    pub name: String,
+    pub source_name: String,
+    // files produced by compilation that are needed to run the program,
+    // for interpreted languages this is the source itself
+    pub artifacts: Vec<String>,
+    pub compile_command: Option<Vec<String>>, // executable followed by its arguments
+    pub run_command: Vec<String>,
+    pub environment: Vec<String>,
+    pub seccomp: SeccompPolicy,              // applied to the run only
+    pub extra_mounts: Vec<(String, String)>, // read-only binds, (host path, path in container)
+    pub compile_limits: Limits,
+    pub run_limits: Limits,
+}
+
+fn in_work_dir(name: &str) -> String {
+    format!("{}/{}", WORK_DIR, name)
+}
+
+fn command(args: &[&str]) -> Vec<String> {
+    args.iter().map(|x| x.to_string()).collect()
+}
+
+// The default policy, denying starting other programs, and threads as well unless `threads`.
+fn seccomp_policy(threads: bool) -> SeccompPolicy {
+    let mut res: SeccompPolicy = Default::default();
+    res.deny_spawn = true;
+    if !threads {
+        res.deny
+            .extend(THREAD_SYSCALLS.iter().map(|x| x.to_string()));
+    }
+    res
+}
+
+fn compile_limits() -> Limits {
+    Limits {
+        time_limit: Duration::from_secs(10),
+        memory_limit: 1024 * 1024 * 1024, // 1Gb
+        fork_limit: 64,
+    }
+}
+
+fn run_limits(fork_limit: u32) -> Limits {
+    Limits {
+        time_limit: Duration::from_secs(1),
+        memory_limit: 256 * 1024 * 1024, // 256Mb
+        fork_limit: fork_limit,
+    }
+}
+
+impl Language {
+    fn native(name: &str, source_name: &str, compile_command: Vec<String>) -> Self {
+        Self {
+            name: name.to_string(),
+            source_name: source_name.to_string(),
+            artifacts: vec!["main".to_string()],
+            compile_command: Some(compile_command),
+            run_command: vec![in_work_dir("main")],
+            environment: vec![DEFAULT_PATH.to_string()],
+            seccomp: seccomp_policy(false),
+            extra_mounts: Vec::new(),
+            compile_limits: compile_limits(),
+            run_limits: run_limits(4),
+        }
+    }
+
+    pub fn c() -> Self {
+        let source = in_work_dir("main.c");
+        let artifact = in_work_dir("main");
+        Self::native(
+            "c",
+            "main.c",
+            command(&[
+                "/usr/bin/gcc",
+                "-O2",
+                "-std=c11",
+                "-o",
+                &artifact,
+                &source,
+                "-lm",
+            ]),
+        )
+    }
+
+    pub fn cpp() -> Self {
+        let source = in_work_dir("main.cpp");
+        let artifact = in_work_dir("main");
+        Self::native(
+            "cpp",
+            "main.cpp",
+            command(&[
+                "/usr/bin/g++",
+                "-O2",
+                "-std=c++17",
+                "-o",
+                &artifact,
+                &source,
+            ]),
+        )
+    }
+
+    pub fn rust() -> Self {
+        let source = in_work_dir("main.rs");
+        let artifact = in_work_dir("main");
+        Self::native(
+            "rust",
+            "main.rs",
+            command(&["/usr/bin/rustc", "-O", "-o", &artifact, &source]),
+        )
+    }
+
+    pub fn pascal() -> Self {
+        let source = in_work_dir("main.pas");
+        let artifact = format!("-o{}", in_work_dir("main"));
+        Self::native(
+            "pascal",
+            "main.pas",
+            command(&["/usr/bin/fpc", "-O2", &artifact, &source]),
+        )
+    }
+
+    pub fn go() -> Self {
+        let source = in_work_dir("main.go");
+        let artifact = in_work_dir("main");
+        let mut res = Self::native(
+            "go",
+            "main.go",
+            command(&["/usr/bin/go", "build", "-o", &artifact, &source]),
+        );
+        res.environment.push("HOME=/tmp".to_string());
+        res.environment.push("GOCACHE=/tmp/go-cache".to_string());
+        // the runtime starts a thread per CPU in use, and more for blocking system calls
+        res.seccomp = seccomp_policy(true);
+        res.run_limits = run_limits(256);
+        res
+    }
+
+    pub fn java() -> Self {
+        let script = format!(
+            "javac -d {0}/classes {0}/Main.java && jar cf {0}/Main.jar -C {0}/classes .",
+            WORK_DIR
+        );
+        Self {
+            name: "java".to_string(),
+            source_name: "Main.java".to_string(),
+            artifacts: vec!["Main.jar".to_string()],
+            compile_command: Some(command(&["/bin/sh", "-c", &script])),
+            run_command: command(&["/usr/bin/java", "-cp", &in_work_dir("Main.jar"), "Main"]),
+            environment: vec![DEFAULT_PATH.to_string()],
+            // the JVM starts tens of threads, for GC and JIT compilers in numbers growing with CPUs
+            seccomp: seccomp_policy(true),
+            extra_mounts: Vec::new(),
+            compile_limits: compile_limits(),
+            run_limits: run_limits(256),
+        }
+    }
+
+    pub fn python() -> Self {
+        Self {
+            name: "python".to_string(),
+            source_name: "main.py".to_string(),
+            artifacts: vec!["main.py".to_string()],
+            compile_command: None,
+            run_command: command(&["/usr/bin/python3", &in_work_dir("main.py")]),
+            environment: vec![DEFAULT_PATH.to_string()],
+            seccomp: seccomp_policy(false),
+            extra_mounts: Vec::new(),
+            compile_limits: compile_limits(),
+            run_limits: run_limits(4),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct LanguageRegistry {
+    languages: HashMap<String, Language>,
+}
+
+impl LanguageRegistry {
+    pub fn new() -> Self {
+        Self {
+            languages: HashMap::new(),
+        }
+    }
+
+    pub fn register(&mut self, language: Language) -> &mut Self {
+        self.languages.insert(language.name.clone(), language);
+        self
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Language> {
+        self.languages.get(name)
+    }
+
+    pub fn get_mut(&mut self, name: &str) -> Option<&mut Language> {
+        self.languages.get_mut(name)
+    }
+}
+
+impl Default for LanguageRegistry {
+    fn default() -> Self {
+        let mut res = Self::new();
+        res.register(Language::c())
+            .register(Language::cpp())
+            .register(Language::rust())
+            .register(Language::java())
+            .register(Language::python())
+            .register(Language::go())
+            .register(Language::pascal());
+        res
+    }
+}
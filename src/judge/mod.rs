@@ -0,0 +1,28 @@
+use crate::container::ExitReport;
+
+pub mod batch;
+pub mod checker;
+pub mod language;
+pub mod pipeline;
+
+pub use batch::{BatchRunner, TestCase};
+pub use checker::Checker;
+pub use language::{Language, LanguageRegistry};
+pub use pipeline::{Pipeline, PipelineResult};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Verdict {
+    Accepted,
+    WrongAnswer,
+    PresentationError,
+    Points(f64),
+    PartiallyCorrect(i32), // the value passed to `_pc()` of testlib
+    Fail,                  // the checker itself failed
+}
+
+#[derive(Debug, Clone)]
+pub struct CheckResult {
+    pub verdict: Verdict,
+    pub message: String,
+    pub report: ExitReport,
+}
@@ -0,0 +1,221 @@
+use {
+    super::{
+        batch::BatchRunner,
+        language::{Language, Limits, WORK_DIR},
+    },
+    crate::{
+        container::{Config, Container, ExitReport, Input, Output},
+        filesystem::{
+            MountExtraFs, MountProcFs, MountReadOnlyBindFs, MountReadOnlyExtraFs,
+            MountReadOnlyFilesFs, MountTmpFs,
+        },
+        security::CapabilityPolicy,
+        CommonResult,
+    },
+    std::{fs, path::PathBuf},
+};
+
+const COMPILER_OUTPUT_LIMIT: usize = 64 * 1024; // 64kb
+
+#[derive(Debug, Clone)]
+pub struct CompileError {
+    pub output: String, // stdout followed by stderr of the compiler
+    pub report: ExitReport,
+}
+
+#[derive(Debug, Clone)]
+pub enum PipelineResult {
+    CompileError(CompileError),
+    Finished(ExitReport),
+}
+
+// Compiled program, kept in a directory on the host until dropped.
+#[derive(Debug)]
+pub struct Artifact {
+    dir: PathBuf,
+    files: Vec<String>,
+}
+
+impl Artifact {
+    pub fn path(&self) -> &std::path::Path {
+        &self.dir
+    }
+
+    pub fn files(&self) -> &Vec<String> {
+        &self.files
+    }
+}
+
+impl Drop for Artifact {
+    #[allow(unused_must_use)]
+    fn drop(&mut self) {
+        fs::remove_dir_all(&self.dir);
+    }
+}
+
+// Compiles a program in one container, then runs it in another fresh one.
+// Both containers use `image` as their root filesystem.
+#[derive(Debug, Clone)]
+pub struct Pipeline<'a> {
+    language: &'a Language,
+    image: String,
+    working_path: String,
+    cgroup_parent: Option<String>, // set when running without root
+}
+
+impl<'a> Pipeline<'a> {
+    pub fn new(language: &'a Language, image: String) -> Self {
+        Self {
+            language: language,
+            image: image,
+            working_path: Config::default().working_path,
+            cgroup_parent: None,
+        }
+    }
+
+    pub fn set_working_path(&mut self, value: String) -> &mut Self {
+        self.working_path = value;
+        self
+    }
+
+    // Runs containers without root, with their cgroups under `cgroup_parent`, which is usually
+    // found by `resource::delegated_parent()`.
+    pub fn set_rootless(&mut self, cgroup_parent: String) -> &mut Self {
+        self.cgroup_parent = Some(cgroup_parent);
+        self
+    }
+
+    fn base_config(&self, limits: &Limits, command: &[String], mount_image: bool) -> Config {
+        let mut config: Config = Default::default();
+        config.working_path = self.working_path.clone();
+        config.fs.push(box MountTmpFs);
+        config.fs.push(box MountProcFs);
+        if mount_image {
+            config.fs.push(box MountReadOnlyBindFs::from(self.image.clone()));
+        }
+        for (source, inner) in self.language.extra_mounts.iter() {
+            config.fs.push(box MountReadOnlyExtraFs::build(source.clone(), inner.clone()));
+        }
+
+        config.target_executable = command[0].clone();
+        config.args = command[1..].to_vec();
+        config.environment = self.language.environment.clone();
+        config.time_limit = limits.time_limit;
+        config
+            .cgroup_limits
+            .set_memory_limit(limits.memory_limit)
+            .set_fork_limit(limits.fork_limit);
+        if let Some(parent) = &self.cgroup_parent {
+            config.rootless = true;
+            config.cgroup_limits.set_parent(parent.clone());
+        }
+        config
+    }
+
+    fn make_dir(&self, kind: &str) -> CommonResult<PathBuf> {
+        let dir = PathBuf::from(&self.working_path).join(format!(
+            "{}.{}.{}",
+            self.language.name,
+            kind,
+            rand::random::<u64>()
+        ));
+        fs::create_dir_all(&dir)?;
+        Ok(dir)
+    }
+
+    pub fn compile(&self, source: &[u8]) -> CommonResult<Result<Artifact, CompileError>> {
+        let build_dir = self.make_dir("build")?;
+        let res = self.do_compile(&build_dir, source);
+        fs::remove_dir_all(&build_dir)?;
+        res
+    }
+
+    fn do_compile(
+        &self,
+        build_dir: &PathBuf,
+        source: &[u8],
+    ) -> CommonResult<Result<Artifact, CompileError>> {
+        fs::write(build_dir.join(&self.language.source_name), source)?;
+
+        if let Some(command) = &self.language.compile_command {
+            let mut config = self.base_config(&self.language.compile_limits, command, true);
+            config.fs.push(box MountExtraFs::build(
+                build_dir.to_string_lossy().into_owned(),
+                WORK_DIR.to_string(),
+            ));
+
+            let mut container = Container::from(config);
+            container
+                .set_stdout(Output::Capture(COMPILER_OUTPUT_LIMIT))
+                .set_stderr(Output::Capture(COMPILER_OUTPUT_LIMIT));
+            container.start()?;
+            let report = container.wait()?;
+            if !report.status.success() {
+                let mut output = String::from_utf8_lossy(&report.stdout).into_owned();
+                output.push_str(&String::from_utf8_lossy(&report.stderr));
+                return Ok(Err(CompileError {
+                    output: output,
+                    report: report,
+                }));
+            }
+        }
+
+        let artifact = Artifact {
+            dir: self.make_dir("artifact")?,
+            files: self.language.artifacts.clone(),
+        };
+        for name in artifact.files.iter() {
+            fs::copy(build_dir.join(name), artifact.dir.join(name))?;
+        }
+        Ok(Ok(artifact))
+    }
+
+    // Config of a container running the artifact, IO and further limits are up to the caller.
+    pub fn run_config(&self, artifact: &Artifact) -> Config {
+        self.artifact_config(artifact, true)
+    }
+
+    // Runs the artifact against many test cases, see `BatchRunner`.
+    pub fn batch_runner(&self, artifact: &Artifact) -> BatchRunner {
+        BatchRunner::new(self.artifact_config(artifact, false), self.image.clone())
+    }
+
+    fn artifact_config(&self, artifact: &Artifact, mount_image: bool) -> Config {
+        let run_command = &self.language.run_command;
+        let mut config = self.base_config(&self.language.run_limits, run_command, mount_image);
+        let mut files = MountReadOnlyFilesFs::new(WORK_DIR.to_string());
+        for name in artifact.files.iter() {
+            files.add_file(
+                name.clone(),
+                artifact.dir.join(name).to_string_lossy().into_owned(),
+            );
+        }
+        config.fs.push(box files);
+        config.security_policies = vec![
+            box (Default::default(): CapabilityPolicy),
+            box self.language.seccomp.clone(),
+        ];
+        config
+    }
+
+    pub fn run(
+        &self,
+        source: &[u8],
+        stdin: Input,
+        stdout: Output,
+        stderr: Output,
+    ) -> CommonResult<PipelineResult> {
+        let artifact = match self.compile(source)? {
+            Ok(x) => x,
+            Err(e) => return Ok(PipelineResult::CompileError(e)),
+        };
+
+        let mut container = Container::from(self.run_config(&artifact));
+        container
+            .set_stdin(stdin)
+            .set_stdout(stdout)
+            .set_stderr(stderr);
+        container.start()?;
+        Ok(PipelineResult::Finished(container.wait()?))
+    }
+}
@@ -5,12 +5,16 @@
 
 extern crate rand;
 extern crate nix;
+extern crate libc;
 extern crate libscmp;
 extern crate caps;
 extern crate cgroups_rs;
 
 pub mod container;
 pub mod filesystem;
+pub mod interactive;
+pub mod judge;
+pub mod pool;
 pub mod security;
 pub mod resource;
 mod idmap;
@@ -0,0 +1,14 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    RefillFailed(u32, String), // failures in a row, and the last one of them
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for Error {}
@@ -0,0 +1,170 @@
+use {
+    crate::{container::Container, CommonResult},
+    std::{
+        collections::VecDeque,
+        sync::{Arc, Condvar, Mutex},
+        thread::JoinHandle,
+        time::Duration,
+    },
+};
+
+mod error;
+
+pub use error::Error;
+
+// Failed attempts are retried after an interval doubling with each one in a row, until so many of
+// them have failed that refilling is given up.
+const RETRY_INTERVAL: Duration = Duration::from_millis(100);
+const MAX_RETRY_INTERVAL: Duration = Duration::from_secs(10);
+const MAX_FAILURES: u32 = 10;
+
+type Factory = dyn Fn() -> Container + Send + Sync;
+
+#[derive(Default)]
+struct Slots {
+    ready: VecDeque<Container>,
+    stopped: bool,
+    failures: u32, // in a row
+    last_error: Option<String>,
+}
+
+struct Shared {
+    factory: Box<Factory>,
+    size: usize,
+    slots: Mutex<Slots>,
+    changed: Condvar,
+}
+
+impl Shared {
+    fn make_slot(&self) -> CommonResult<Container> {
+        let mut container = (self.factory)();
+        container.prepare()?;
+        Ok(container)
+    }
+
+    // Keeps the pool filled up in the background, until it is stopped.
+    fn refill(&self) {
+        loop {
+            {
+                let mut slots = self.slots.lock().unwrap();
+                while !slots.stopped
+                    && (slots.ready.len() >= self.size || slots.failures >= MAX_FAILURES)
+                {
+                    slots = self.changed.wait(slots).unwrap();
+                }
+                if slots.stopped {
+                    return;
+                }
+            }
+
+            let res = self.make_slot();
+            let mut slots = self.slots.lock().unwrap();
+            match res {
+                Ok(container) => {
+                    slots.failures = 0;
+                    slots.ready.push_back(container);
+                    self.changed.notify_all();
+                }
+                Err(err) => {
+                    slots.failures += 1;
+                    slots.last_error = Some(err.to_string());
+                    let interval = RETRY_INTERVAL * 2_u32.pow(slots.failures - 1);
+                    let interval = std::cmp::min(interval, MAX_RETRY_INTERVAL);
+                    let _ = self
+                        .changed
+                        .wait_timeout_while(slots, interval, |x| !x.stopped);
+                }
+            }
+        }
+    }
+}
+
+// A pool of prepared containers, every one of them has been created and set up, and only has to
+// execute its target via `Container::launch()`.
+pub struct ContainerPool {
+    shared: Arc<Shared>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl std::fmt::Debug for ContainerPool {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("ContainerPool")
+            .field("size", &self.shared.size)
+            .finish()
+    }
+}
+
+impl ContainerPool {
+    // `factory` makes unstarted containers, each one is given a fresh config (e.g. a new uid).
+    pub fn new<F>(size: usize, factory: F) -> Self
+    where
+        F: Fn() -> Container + Send + Sync + 'static,
+    {
+        let shared = Arc::new(Shared {
+            factory: box factory,
+            size: size,
+            slots: Default::default(),
+            changed: Condvar::new(),
+        });
+
+        let worker_shared = shared.clone();
+        Self {
+            shared: shared,
+            worker: Some(std::thread::spawn(move || worker_shared.refill())),
+        }
+    }
+
+    // Takes a prepared container out of the pool, preparing one on the spot if none is ready.
+    // Fails without trying once refilling has been given up, see `retry()`.
+    pub fn acquire(&self) -> CommonResult<Container> {
+        {
+            let mut slots = self.shared.slots.lock().unwrap();
+            if let Some(container) = slots.ready.pop_front() {
+                self.shared.changed.notify_all();
+                return Ok(container);
+            }
+            if slots.failures >= MAX_FAILURES {
+                let last_error = slots.last_error.clone().unwrap_or_default();
+                return Err(Box::new(Error::RefillFailed(slots.failures, last_error)));
+            }
+        }
+        self.shared.make_slot()
+    }
+
+    pub fn ready_count(&self) -> usize {
+        self.shared.slots.lock().unwrap().ready.len()
+    }
+
+    // Why preparing the last container in the background failed, if it did.
+    pub fn last_error(&self) -> Option<String> {
+        let slots = self.shared.slots.lock().unwrap();
+        match slots.failures {
+            0 => None,
+            _ => slots.last_error.clone(),
+        }
+    }
+
+    // Starts refilling again after it has been given up, e.g. once the cause has been fixed.
+    pub fn retry(&self) {
+        let mut slots = self.shared.slots.lock().unwrap();
+        slots.failures = 0;
+        self.shared.changed.notify_all();
+    }
+}
+
+impl Drop for ContainerPool {
+    fn drop(&mut self) {
+        {
+            let mut slots = self.shared.slots.lock().unwrap();
+            slots.stopped = true;
+            self.shared.changed.notify_all();
+        }
+
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+
+        // the prepared containers are killed and deleted as they are dropped
+        self.shared.slots.lock().unwrap().ready.clear();
+    }
+}
@@ -0,0 +1,175 @@
+use {
+    super::controller_failed,
+    crate::VoidResult,
+    cgroups_rs::{cpu::CpuController, Controller},
+    std::{fs, time::Duration},
+};
+
+const DEFAULT_PERIOD: Duration = Duration::from_millis(100);
+const MIN_QUOTA: u64 = 1000; // in microseconds, the kernel refuses anything less
+
+// CPU bandwidth and weight of a cgroup.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+pub(super) struct CpuPolicy {
+    pub limit: Option<f64>,       // in CPUs, e.g. 1.5 for one and a half cores
+    pub period: Option<Duration>, // in which the limit is enforced
+    pub weight: Option<u64>,      // from 1 to 10000, relative to other cgroups, 100 by default
+    pub burst: Option<Duration>,  // unused quota which may be accumulated and spent later
+}
+
+impl CpuPolicy {
+    pub fn is_set(&self) -> bool {
+        *self != Default::default()
+    }
+
+    pub fn apply(&self, control: &CpuController, v2: bool) -> VoidResult {
+        let period = self.period.unwrap_or(DEFAULT_PERIOD).as_micros() as u64;
+        let quota = self
+            .limit
+            .map(|x| std::cmp::max((x * period as f64).round() as u64, MIN_QUOTA) as i64);
+        if quota.is_some() || self.period.is_some() {
+            control
+                .set_cfs_quota_and_period(quota, Some(period))
+                .map_err(controller_failed("cpu"))?;
+        }
+
+        if let Some(weight) = self.weight {
+            // cpu.shares of cgroup v1 is 1024 by default, converted the same way as systemd does
+            let value = if v2 {
+                weight
+            } else {
+                std::cmp::max(weight * 1024 / 100, 2)
+            };
+            control
+                .set_shares(value)
+                .map_err(controller_failed("cpu"))?;
+        }
+
+        // only available since Linux 5.14
+        if let Some(burst) = self.burst {
+            let file = if v2 {
+                "cpu.max.burst"
+            } else {
+                "cpu.cfs_burst_us"
+            };
+            fs::write(control.path().join(file), burst.as_micros().to_string())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {super::*, std::path::PathBuf};
+
+    // A directory standing for a cgroup, with the files which are read before written.
+    struct FakeCgroup {
+        path: PathBuf,
+    }
+
+    impl FakeCgroup {
+        fn new() -> Self {
+            let path =
+                std::env::temp_dir().join(format!("ssandbox.rs.test.{}", rand::random::<u64>()));
+            fs::create_dir_all(&path).unwrap();
+            fs::write(path.join("cpu.max"), "max 100000\n").unwrap();
+            Self { path: path }
+        }
+
+        fn controller(&self, v2: bool) -> CpuController {
+            CpuController::new(self.path.clone(), v2)
+        }
+
+        fn read(&self, file: &str) -> String {
+            fs::read_to_string(self.path.join(file)).unwrap()
+        }
+    }
+
+    impl Drop for FakeCgroup {
+        fn drop(&mut self) {
+            let _ = fs::remove_dir_all(&self.path);
+        }
+    }
+
+    #[test]
+    fn fraction_of_cpus_v1() {
+        let cg = FakeCgroup::new();
+        let policy = CpuPolicy {
+            limit: Some(1.5),
+            ..Default::default()
+        };
+        policy.apply(&cg.controller(false), false).unwrap();
+        assert_eq!(cg.read("cpu.cfs_quota_us"), "150000");
+        assert_eq!(cg.read("cpu.cfs_period_us"), "100000");
+    }
+
+    #[test]
+    fn fraction_of_cpus_with_period_v2() {
+        let cg = FakeCgroup::new();
+        let policy = CpuPolicy {
+            limit: Some(0.5),
+            period: Some(Duration::from_millis(50)),
+            ..Default::default()
+        };
+        policy.apply(&cg.controller(true), true).unwrap();
+        assert_eq!(cg.read("cpu.max"), "25000 50000");
+    }
+
+    #[test]
+    fn period_only_v2() {
+        let cg = FakeCgroup::new();
+        let policy = CpuPolicy {
+            period: Some(Duration::from_millis(20)),
+            ..Default::default()
+        };
+        policy.apply(&cg.controller(true), true).unwrap();
+        assert_eq!(cg.read("cpu.max"), "max 20000");
+    }
+
+    #[test]
+    fn tiny_limit_is_raised_to_minimum() {
+        let cg = FakeCgroup::new();
+        let policy = CpuPolicy {
+            limit: Some(0.001),
+            ..Default::default()
+        };
+        policy.apply(&cg.controller(true), true).unwrap();
+        assert_eq!(cg.read("cpu.max"), "1000 100000");
+    }
+
+    #[test]
+    fn weight() {
+        let cg = FakeCgroup::new();
+        let policy = CpuPolicy {
+            weight: Some(200),
+            ..Default::default()
+        };
+        policy.apply(&cg.controller(true), true).unwrap();
+        assert_eq!(cg.read("cpu.weight"), "200");
+        policy.apply(&cg.controller(false), false).unwrap();
+        assert_eq!(cg.read("cpu.shares"), "2048");
+    }
+
+    #[test]
+    fn burst() {
+        let cg = FakeCgroup::new();
+        let policy = CpuPolicy {
+            burst: Some(Duration::from_millis(20)),
+            ..Default::default()
+        };
+        policy.apply(&cg.controller(true), true).unwrap();
+        assert_eq!(cg.read("cpu.max.burst"), "20000");
+        policy.apply(&cg.controller(false), false).unwrap();
+        assert_eq!(cg.read("cpu.cfs_burst_us"), "20000");
+    }
+
+    #[test]
+    fn nothing_written_if_unset() {
+        let cg = FakeCgroup::new();
+        CpuPolicy::default()
+            .apply(&cg.controller(false), false)
+            .unwrap();
+        assert!(!cg.path.join("cpu.cfs_quota_us").exists());
+        assert!(!cg.path.join("cpu.shares").exists());
+    }
+}
@@ -0,0 +1,119 @@
+use {
+    super::error::Error,
+    crate::CommonResult,
+    std::{
+        collections::{BTreeSet, HashMap},
+        fs,
+        sync::Mutex,
+    },
+};
+
+// Parses a list in the format of cpuset.cpus, e.g. "0-3,8".
+pub fn parse_list(value: &str) -> Option<Vec<usize>> {
+    let mut res = Vec::new();
+    for part in value.trim().split(',').filter(|x| !x.is_empty()) {
+        match part.split_once('-') {
+            Some((first, last)) => res.extend(first.parse::<usize>().ok()?..=last.parse().ok()?),
+            None => res.push(part.parse().ok()?),
+        }
+    }
+    Some(res)
+}
+
+pub fn format_list(value: &[usize]) -> String {
+    value
+        .iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+// Hands out CPUs to containers running at the same time, so that no two of them share one.
+// The CPUs of a container are taken when its cgroup is applied, and given back when it is deleted.
+#[derive(Debug)]
+pub struct CpuAllocator {
+    free: Mutex<BTreeSet<usize>>,
+    taken: Mutex<HashMap<u64, Vec<usize>>>, // by the uid of containers
+}
+
+impl CpuAllocator {
+    pub fn new(cpus: Vec<usize>) -> Self {
+        Self {
+            free: Mutex::new(cpus.into_iter().collect()),
+            taken: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // Uses the CPUs isolated from the scheduler by the `isolcpus` boot parameter, on which
+    // nothing else runs.
+    pub fn isolated() -> CommonResult<Self> {
+        let content = fs::read_to_string("/sys/devices/system/cpu/isolated")?;
+        match parse_list(&content) {
+            Some(cpus) if cpus.is_empty() => Err(Box::new(Error::NoIsolatedCpus)),
+            Some(cpus) => Ok(Self::new(cpus)),
+            None => Err(box Error::MalformedCpuList(content)),
+        }
+    }
+
+    pub fn available(&self) -> usize {
+        self.free.lock().unwrap().len()
+    }
+
+    pub(super) fn allocate(&self, uid: u64, count: usize) -> CommonResult<Vec<usize>> {
+        let mut taken = self.taken.lock().unwrap();
+        if let Some(cpus) = taken.get(&uid) {
+            return Ok(cpus.clone());
+        }
+
+        let mut free = self.free.lock().unwrap();
+        if free.len() < count {
+            return Err(box Error::NotEnoughCpus(count, free.len()));
+        }
+        let cpus: Vec<usize> = free.iter().take(count).cloned().collect();
+        for cpu in cpus.iter() {
+            free.remove(cpu);
+        }
+        taken.insert(uid, cpus.clone());
+        Ok(cpus)
+    }
+
+    pub(super) fn release(&self, uid: u64) {
+        if let Some(cpus) = self.taken.lock().unwrap().remove(&uid) {
+            self.free.lock().unwrap().extend(cpus);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!(parse_list("0-3,8\n"), Some(vec![0, 1, 2, 3, 8]));
+        assert_eq!(parse_list("5"), Some(vec![5]));
+        assert_eq!(parse_list("\n"), Some(vec![]));
+        assert_eq!(parse_list("0-x"), None);
+        assert_eq!(format_list(&[0, 1, 8]), "0,1,8");
+    }
+
+    #[test]
+    fn allocate_and_release() {
+        let allocator = CpuAllocator::new(vec![0, 1, 2, 3]);
+        assert_eq!(allocator.allocate(1, 2).unwrap(), vec![0, 1]);
+        assert_eq!(allocator.allocate(1, 2).unwrap(), vec![0, 1]);
+        assert_eq!(allocator.allocate(2, 2).unwrap(), vec![2, 3]);
+        assert_eq!(allocator.available(), 0);
+
+        let err = allocator.allocate(3, 1).unwrap_err();
+        match err.downcast_ref::<Error>() {
+            Some(Error::NotEnoughCpus(1, 0)) => {}
+            x => panic!("unexpected {:?}", x),
+        }
+
+        allocator.release(1);
+        allocator.release(1);
+        assert_eq!(allocator.available(), 2);
+        assert_eq!(allocator.allocate(3, 1).unwrap(), vec![0]);
+    }
+}
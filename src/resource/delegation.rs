@@ -0,0 +1,76 @@
+use {
+    super::error::Error,
+    crate::CommonResult,
+    nix::unistd::{self, AccessFlags},
+    std::{fs, path::Path, sync::Mutex},
+};
+
+const UNIFIED_MOUNTPOINT: &str = "/sys/fs/cgroup";
+
+// Processes are not allowed in a cgroup whose controllers are enabled for its children, so this
+// process moves itself into a leaf beside the cgroups of containers.
+const SUPERVISOR_CGROUP: &str = "ssandbox.rs.supervisor";
+
+static DELEGATED_PARENT: Mutex<Option<String>> = Mutex::new(None);
+
+fn not_delegated(reason: String) -> Box<dyn std::error::Error> {
+    box Error::NotDelegated(reason)
+}
+
+// The cgroup v2 of this process, relative to the root of the hierarchy.
+fn current_cgroup() -> CommonResult<String> {
+    let content = fs::read_to_string("/proc/self/cgroup")?;
+    match content.lines().find_map(|line| line.strip_prefix("0::")) {
+        Some(path) => Ok(path.trim_start_matches('/').to_string()),
+        None => Err(not_delegated("not in a cgroup v2".to_string())),
+    }
+}
+
+// Finds the cgroup v2 subtree delegated to this process, under which containers can be created
+// without root, and moves this process out of the way. It is the cgroup this process is started
+// in, e.g. by `systemd-run --user --scope -p Delegate=yes`.
+//
+// Returns its path relative to the root of the hierarchy, to be given to
+// `CGroupLimitPolicy::set_parent()`.
+pub fn delegated_parent() -> CommonResult<String> {
+    let mut cached = DELEGATED_PARENT.lock().unwrap();
+    if let Some(parent) = &*cached {
+        return Ok(parent.clone());
+    }
+
+    if !cgroups_rs::hierarchies::is_cgroup2_unified_mode() {
+        return Err(not_delegated(format!(
+            "cgroup v2 is not mounted at {}",
+            UNIFIED_MOUNTPOINT
+        )));
+    }
+    let mut parent = current_cgroup()?;
+    if let Some(x) = parent.strip_suffix(&format!("/{}", SUPERVISOR_CGROUP)) {
+        parent = x.to_string();
+    }
+    if parent.is_empty() {
+        return Err(not_delegated("in the root cgroup".to_string()));
+    }
+
+    let path = Path::new(UNIFIED_MOUNTPOINT).join(&parent);
+    for file in ["", "cgroup.procs", "cgroup.subtree_control"].iter() {
+        if unistd::access(&path.join(file), AccessFlags::W_OK).is_err() {
+            return Err(not_delegated(format!(
+                "{} is not writable, try starting with `systemd-run --user --scope -p Delegate=yes`",
+                path.join(file).display()
+            )));
+        }
+    }
+
+    let supervisor = path.join(SUPERVISOR_CGROUP);
+    if !supervisor.exists() {
+        fs::create_dir(&supervisor)?;
+    }
+    fs::write(
+        supervisor.join("cgroup.procs"),
+        unistd::getpid().to_string(),
+    )?;
+
+    *cached = Some(parent.clone());
+    Ok(parent)
+}
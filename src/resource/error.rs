@@ -0,0 +1,29 @@
+use std::fmt;
+
+#[derive(Debug)]
+pub enum Error {
+    CGroupNotEmpty(Vec<u64>), // pids still in the cgroup
+    ControllerFailed(String, cgroups_rs::error::Error), // name of the controller, and the cause
+    NotDelegated(String),     // why no cgroup can be used without root
+    CGroupNotFound(String),   // path of the cgroup expected to exist
+    NotEnoughCpus(usize, usize), // CPUs requested, and those left
+    MalformedCpuList(String),
+    NoIsolatedCpus,                // isolcpus is not set on the kernel command line
+    ControllerMissing(String),     // name of a controller needed by the policy
+    MalformedNameTemplate(String), // lacks `{uid}`, so containers would share a cgroup
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:?}", self)
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Error::ControllerFailed(_, cause) => Some(cause),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,107 @@
+use {
+    crate::CommonResult,
+    cgroups_rs::blkio::{BlkIoController, IoService},
+    nix::sys::stat,
+};
+
+// Throttling of the IO on one block device, unset rates are not limited.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IoLimit {
+    pub major: u64,
+    pub minor: u64,
+    pub read_bps: Option<u64>,
+    pub write_bps: Option<u64>,
+    pub read_iops: Option<u64>,
+    pub write_iops: Option<u64>,
+}
+
+impl IoLimit {
+    pub fn new(major: u64, minor: u64) -> Self {
+        Self {
+            major: major,
+            minor: minor,
+            ..Default::default()
+        }
+    }
+
+    // For the block device at `path`, e.g. `/dev/sda`. Throttling only works on whole disks.
+    pub fn for_device(path: &str) -> CommonResult<Self> {
+        let rdev = stat::stat(path)?.st_rdev;
+        Ok(Self::new(stat::major(rdev), stat::minor(rdev)))
+    }
+
+    pub fn set_read_bps(&mut self, value: u64) -> &mut Self {
+        self.read_bps = Some(value);
+        self
+    }
+
+    pub fn set_write_bps(&mut self, value: u64) -> &mut Self {
+        self.write_bps = Some(value);
+        self
+    }
+
+    pub fn set_read_iops(&mut self, value: u64) -> &mut Self {
+        self.read_iops = Some(value);
+        self
+    }
+
+    pub fn set_write_iops(&mut self, value: u64) -> &mut Self {
+        self.write_iops = Some(value);
+        self
+    }
+
+    pub(super) fn apply(&self, control: &BlkIoController) -> cgroups_rs::error::Result<()> {
+        let (major, minor) = (self.major, self.minor);
+        if let Some(x) = self.read_bps {
+            control.throttle_read_bps_for_device(major, minor, x)?;
+        }
+        if let Some(x) = self.write_bps {
+            control.throttle_write_bps_for_device(major, minor, x)?;
+        }
+        if let Some(x) = self.read_iops {
+            control.throttle_read_iops_for_device(major, minor, x)?;
+        }
+        if let Some(x) = self.write_iops {
+            control.throttle_write_iops_for_device(major, minor, x)?;
+        }
+        Ok(())
+    }
+}
+
+// IO done by the processes of a cgroup, summed up over all block devices.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct IoUsage {
+    pub read_bytes: u64,
+    pub write_bytes: u64,
+    pub read_ios: u64,
+    pub write_ios: u64,
+}
+
+impl IoUsage {
+    // From io.stat on cgroup v2, and the throttling statistics on cgroup v1, which are counted
+    // even if nothing is throttled.
+    pub(super) fn read(control: &BlkIoController, v2: bool) -> Self {
+        let stat = control.blkio();
+        if v2 {
+            return stat.io_stat.iter().fold(Default::default(), |res, x| Self {
+                read_bytes: res.read_bytes + x.rbytes,
+                write_bytes: res.write_bytes + x.wbytes,
+                read_ios: res.read_ios + x.rios,
+                write_ios: res.write_ios + x.wios,
+            });
+        }
+
+        let sum = |x: &Vec<IoService>| {
+            x.iter()
+                .fold((0, 0), |(read, write), x| (read + x.read, write + x.write))
+        };
+        let (read_bytes, write_bytes) = sum(&stat.throttle.io_service_bytes);
+        let (read_ios, write_ios) = sum(&stat.throttle.io_serviced);
+        Self {
+            read_bytes: read_bytes,
+            write_bytes: write_bytes,
+            read_ios: read_ios,
+            write_ios: write_ios,
+        }
+    }
+}
@@ -1,17 +1,58 @@
 use {
-    crate::VoidResult,
+    crate::{CommonResult, VoidResult},
     cgroups_rs::{
-        cpu::CpuController, freezer::FreezerController, memory::MemController, pid::PidController,
-        MaxValue,
+        blkio::BlkIoController, cgroup::Cgroup, cpu::CpuController, cpuset::CpuSetController,
+        freezer::FreezerController, memory::MemController, pid::PidController, MaxValue,
     },
+    nix::{
+        sys::signal::{self, Signal},
+        unistd::Pid,
+    },
+    std::{fs, sync::Arc, time::Duration},
 };
 
+mod cpu;
+mod cpuset;
+mod delegation;
+mod error;
+mod io;
+mod monitor;
+pub mod rlimit;
+
+pub use cpuset::CpuAllocator;
+pub use delegation::delegated_parent;
+pub use error::Error;
+pub use io::{IoLimit, IoUsage};
+pub use monitor::{CGroupEvent, CGroupEvents, CGroupMonitor};
+pub use rlimit::RlimitPolicy;
+
+// A template without `{uid}` is only allowed for existing cgroups, as deleting a container would
+// otherwise kill the processes of another one and remove its cgroup.
+fn check_name_template(template: &str, attach_existing: bool) -> Result<(), Error> {
+    if !template.contains("{uid}") && !attach_existing {
+        return Err(Error::MalformedNameTemplate(template.to_string()));
+    }
+    Ok(())
+}
+
+fn controller_failed(name: &str) -> impl FnOnce(cgroups_rs::error::Error) -> Error + '_ {
+    move |cause| Error::ControllerFailed(name.to_string(), cause)
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct CGroupLimitPolicy {
-    cpu_limit: Option<i64>,
+    cpu: cpu::CpuPolicy,
     time_limit: Option<u64>,
     memory_limit: Option<i64>,
     fork_limit: Option<u32>,
+    cpus: Option<Vec<usize>>, // CPUs and memory nodes the container is pinned to
+    mems: Option<Vec<usize>>,
+    // with the number of CPUs each container takes from it
+    cpu_allocator: Option<(Arc<CpuAllocator>, usize)>,
+    io_limits: Vec<IoLimit>,       // one for each block device
+    parent: Option<String>,        // relative to the root of the hierarchy
+    name_template: Option<String>, // `{uid}` in it is replaced by the uid of the container
+    attach_existing: bool,
 }
 
 impl CGroupLimitPolicy {
@@ -30,66 +71,263 @@ impl CGroupLimitPolicy {
         self
     }
 
-    pub fn set_cpu_limit(&mut self, value: i64) -> &mut Self {
-        self.cpu_limit = Some(value);
+    // Limits the CPU time to `cores` CPUs, e.g. 1.5 for one and a half.
+    pub fn set_cpu_limit(&mut self, cores: f64) -> &mut Self {
+        self.cpu.limit = Some(cores);
+        self
+    }
+
+    // The period in which the CPU limit is enforced, 100ms by default. Shorter ones throttle more
+    // evenly, at the cost of more overhead.
+    pub fn set_cpu_period(&mut self, value: Duration) -> &mut Self {
+        self.cpu.period = Some(value);
+        self
+    }
+
+    // The share of CPU time relative to other cgroups when they compete, from 1 to 10000 with 100
+    // by default, as cpu.weight of cgroup v2. It is converted to cpu.shares on cgroup v1.
+    pub fn set_cpu_weight(&mut self, value: u64) -> &mut Self {
+        self.cpu.weight = Some(value);
+        self
+    }
+
+    // Lets up to `value` of unused CPU time be saved up and spent beyond the limit later.
+    pub fn set_cpu_burst(&mut self, value: Duration) -> &mut Self {
+        self.cpu.burst = Some(value);
+        self
+    }
+
+    pub fn set_cpus(&mut self, value: Vec<usize>) -> &mut Self {
+        self.cpus = Some(value);
+        self
+    }
+
+    pub fn set_mems(&mut self, value: Vec<usize>) -> &mut Self {
+        self.mems = Some(value);
+        self
+    }
+
+    // Pins each container to `count` CPUs of its own taken from `allocator`, instead of the ones
+    // set by `set_cpus()`.
+    pub fn set_cpu_allocator(&mut self, allocator: Arc<CpuAllocator>, count: usize) -> &mut Self {
+        self.cpu_allocator = Some((allocator, count));
+        self
+    }
+
+    pub fn clear_cpus(&mut self) -> &mut Self {
+        self.cpus = None;
+        self
+    }
+
+    pub fn clear_mems(&mut self) -> &mut Self {
+        self.mems = None;
+        self
+    }
+
+    pub fn clear_cpu_allocator(&mut self) -> &mut Self {
+        self.cpu_allocator = None;
+        self
+    }
+
+    pub fn add_io_limit(&mut self, value: IoLimit) -> &mut Self {
+        self.io_limits.push(value);
+        self
+    }
+
+    pub fn clear_io_limits(&mut self) -> &mut Self {
+        self.io_limits.clear();
+        self
+    }
+
+    // Creates the cgroups of containers under `path` instead of at the root of the hierarchy, e.g.
+    // `judge.slice/worker-3`, so that limits set on it apply to all of them together. It may also
+    // be the subtree delegated to a user, found by `delegated_parent()`.
+    pub fn set_parent(&mut self, path: String) -> &mut Self {
+        self.parent = Some(path.trim_matches('/').to_string());
+        self
+    }
+
+    pub fn clear_parent(&mut self) -> &mut Self {
+        self.parent = None;
+        self
+    }
+
+    // Names the cgroups of containers after `template` instead of `ssandbox.rs.container.{uid}`.
+    // A template without `{uid}` is only allowed once `set_attach_existing(true)` has been called.
+    pub fn set_name_template(&mut self, template: String) -> Result<&mut Self, Error> {
+        check_name_template(&template, self.attach_existing)?;
+        self.name_template = Some(template);
+        Ok(self)
+    }
+
+    pub fn clear_name_template(&mut self) -> &mut Self {
+        self.name_template = None;
         self
     }
 
+    // Moves containers into cgroups which exist already, set up by the caller, instead of creating
+    // them. Such a cgroup is left behind when the container is deleted, and only the processes of
+    // the container are killed by then, so that it may be shared with other containers.
+    pub fn set_attach_existing(&mut self, value: bool) -> Result<&mut Self, Error> {
+        if let Some(template) = &self.name_template {
+            check_name_template(template, value)?;
+        }
+        self.attach_existing = value;
+        Ok(self)
+    }
+
+    fn name(&self, uid: u64) -> String {
+        let name = match &self.name_template {
+            Some(template) => template.replace("{uid}", &uid.to_string()),
+            None => format!("ssandbox.rs.container.{}", uid),
+        };
+        match &self.parent {
+            Some(parent) => format!("{}/{}", parent, name),
+            None => name,
+        }
+    }
+
     pub fn clear_time_limit(&mut self) -> &mut Self {
         self.time_limit = None;
         self
     }
 
     pub fn clear_memory_limit(&mut self) -> &mut Self {
-        self.time_limit = None;
+        self.memory_limit = None;
         self
     }
 
     pub fn clear_fork_limit(&mut self) -> &mut Self {
-        self.time_limit = None;
+        self.fork_limit = None;
         self
     }
 
     pub fn clear_cpu_limit(&mut self) -> &mut Self {
-        self.time_limit = None;
+        self.cpu.limit = None;
+        self
+    }
+
+    pub fn clear_cpu_period(&mut self) -> &mut Self {
+        self.cpu.period = None;
+        self
+    }
+
+    pub fn clear_cpu_weight(&mut self) -> &mut Self {
+        self.cpu.weight = None;
+        self
+    }
+
+    pub fn clear_cpu_burst(&mut self) -> &mut Self {
+        self.cpu.burst = None;
         self
     }
 
     pub fn apply(&self, uid: u64, pid: nix::unistd::Pid) -> VoidResult {
+        let name = self.name(uid);
         let hier = cgroups_rs::hierarchies::auto();
-        let cg = cgroups_rs::cgroup::Cgroup::new(hier, &format!("ssandbox.rs.container.{}", uid));
+        let cg = if self.attach_existing {
+            let cg = Cgroup::load(hier, &name);
+            if !cg.subsystems().iter().any(|x| x.to_controller().exists()) {
+                return Err(box Error::CGroupNotFound(name));
+            }
+            cg
+        } else {
+            Cgroup::new(hier, &name)
+        };
         cg.add_task(cgroups_rs::CgroupPid::from(pid.as_raw() as u64))?;
 
         if let Some(fork_limit) = self.fork_limit {
             let control: Option<&PidController> = cg.controller_of();
             if let Some(control) = control {
-                control.set_pid_max(MaxValue::Value(fork_limit.into()))?;
+                control
+                    .set_pid_max(MaxValue::Value(fork_limit.into()))
+                    .map_err(controller_failed("pids"))?;
             }
         }
 
-        if let Some(cpu_limit) = self.cpu_limit {
+        if self.cpu.is_set() {
             let control: Option<&CpuController> = cg.controller_of();
             if let Some(control) = control {
-                control.set_cfs_period(50000)?;
-                control.set_cfs_quota(cpu_limit * 50000 / 1000000)?;
+                self.cpu.apply(control, cg.v2())?;
+            }
+        }
+
+        // unlike other limits, pinning is not skipped silently, as CPUs handed out by the
+        // allocator would be reserved for a container which does not run on them
+        if self.cpu_allocator.is_some() || self.cpus.is_some() || self.mems.is_some() {
+            let control: Option<&CpuSetController> = cg.controller_of();
+            let control = match control {
+                Some(control) => control,
+                None => return Err(Box::new(Error::ControllerMissing("cpuset".to_string()))),
+            };
+            let cpus = match &self.cpu_allocator {
+                Some((allocator, count)) => Some(allocator.allocate(uid, *count)?),
+                None => self.cpus.clone(),
+            };
+            // memory nodes go first, a cgroup v1 without them can not take any CPU
+            self.mems
+                .iter()
+                .try_for_each(|x| control.set_mems(&cpuset::format_list(x)))
+                .and_then(|_| {
+                    cpus.iter()
+                        .try_for_each(|x| control.set_cpus(&cpuset::format_list(x)))
+                })
+                .map_err(controller_failed("cpuset"))?;
+        }
+
+        if !self.io_limits.is_empty() {
+            let control: Option<&BlkIoController> = cg.controller_of();
+            if let Some(control) = control {
+                self.io_limits
+                    .iter()
+                    .try_for_each(|x| x.apply(control))
+                    .map_err(controller_failed("io"))?;
             }
         }
 
         if let Some(memory_limit) = self.memory_limit {
             let control: Option<&MemController> = cg.controller_of();
             if let Some(control) = control {
-                control.set_kmem_limit(memory_limit)?;
-                control.set_limit(memory_limit)?;
-                control.set_memswap_limit(memory_limit)?;
+                control
+                    .set_kmem_limit(memory_limit)
+                    .and_then(|_| control.set_limit(memory_limit))
+                    .and_then(|_| control.set_memswap_limit(memory_limit))
+                    .map_err(controller_failed("memory"))?;
             }
         }
 
         Ok(())
     }
 
+    // Moves another process into the cgroup created by `apply()`.
+    pub fn attach(&self, uid: u64, pid: nix::unistd::Pid) -> VoidResult {
+        let hier = cgroups_rs::hierarchies::auto();
+        let cg = Cgroup::load(hier, &self.name(uid));
+        cg.add_task(cgroups_rs::CgroupPid::from(pid.as_raw() as u64))?;
+        Ok(())
+    }
+
+    // IO done in the cgroup created by `apply()` so far.
+    pub fn io_usage(&self, uid: u64) -> IoUsage {
+        let hier = cgroups_rs::hierarchies::auto();
+        let cg = Cgroup::load(hier, &self.name(uid));
+        let control: Option<&BlkIoController> = cg.controller_of();
+        match control {
+            Some(control) => IoUsage::read(control, cg.v2()),
+            None => Default::default(),
+        }
+    }
+
+    // Starts watching the cgroup created by `apply()` for OOM kills and refused forks.
+    pub fn monitor(&self, uid: u64) -> CommonResult<CGroupMonitor> {
+        let hier = cgroups_rs::hierarchies::auto();
+        let cg = Cgroup::load(hier, &self.name(uid));
+        CGroupMonitor::spawn(&cg)
+    }
+
     pub fn freeze(&self, uid: u64) -> VoidResult {
         let hier = cgroups_rs::hierarchies::auto();
-        let cg = cgroups_rs::cgroup::Cgroup::load(hier, &format!("ssandbox.rs.container.{}", uid));
+        let cg = Cgroup::load(hier, &self.name(uid));
         let control: Option<&FreezerController> = cg.controller_of();
         if let Some(freezer) = control {
             freezer.freeze()?;
@@ -99,7 +337,7 @@ impl CGroupLimitPolicy {
 
     pub fn thaw(&self, uid: u64) -> VoidResult {
         let hier = cgroups_rs::hierarchies::auto();
-        let cg = cgroups_rs::cgroup::Cgroup::load(hier, &format!("ssandbox.rs.container.{}", uid));
+        let cg = Cgroup::load(hier, &self.name(uid));
         let control: Option<&FreezerController> = cg.controller_of();
         if let Some(freezer) = control {
             freezer.thaw()?;
@@ -107,10 +345,130 @@ impl CGroupLimitPolicy {
         Ok(())
     }
 
+    // Kills every process in the cgroup, including those which escaped from the init process of
+    // the container, and makes sure that none of them is left. Existing cgroups may be shared, so
+    // nothing is killed in them, the container then goes with its init and its pid namespace.
+    pub fn kill_all(&self, uid: u64) -> VoidResult {
+        const CHECK_TIMES: u32 = 100;
+        const CHECK_INTERVAL: Duration = Duration::from_millis(10);
+
+        if self.attach_existing {
+            return Ok(());
+        }
+
+        let hier = cgroups_rs::hierarchies::auto();
+        let name = self.name(uid);
+        let kill_file = hier.root().join(&name).join("cgroup.kill");
+        let cg = Cgroup::load(hier, &name);
+        if cg.tasks().is_empty() {
+            return Ok(());
+        }
+
+        // cgroup.kill is only available since Linux 5.14
+        if cg.v2() && kill_file.exists() {
+            fs::write(&kill_file, "1")?;
+        } else {
+            // frozen processes can neither fork nor exit, so their pids stay valid while killing
+            self.freeze(uid)?;
+            for task in cg.tasks() {
+                match signal::kill(Pid::from_raw(task.pid as i32), Signal::SIGKILL) {
+                    Ok(()) | Err(nix::Error::Sys(nix::errno::Errno::ESRCH)) => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            // on cgroup v1, frozen processes handle SIGKILL only after thawed
+            self.thaw(uid)?;
+        }
+
+        for _ in 0..CHECK_TIMES {
+            if cg.tasks().is_empty() {
+                return Ok(());
+            }
+            std::thread::sleep(CHECK_INTERVAL);
+        }
+        let tasks = cg.tasks().iter().map(|task| task.pid).collect();
+        Err(box error::Error::CGroupNotEmpty(tasks))
+    }
+
     pub fn delete(&self, uid: u64) -> VoidResult {
+        let res = self.kill_all(uid);
+        if let Some((allocator, _)) = &self.cpu_allocator {
+            allocator.release(uid);
+        }
+        res?;
+        if self.attach_existing {
+            return Ok(());
+        }
         let hier = cgroups_rs::hierarchies::auto();
-        let cg = cgroups_rs::cgroup::Cgroup::load(hier, &format!("ssandbox.rs.container.{}", uid));
+        let cg = Cgroup::load(hier, &self.name(uid));
         cg.delete()?;
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn clear_only_its_own_limit() {
+        let mut policy = CGroupLimitPolicy::default();
+        policy
+            .set_time_limit(1)
+            .set_memory_limit(2)
+            .set_fork_limit(3)
+            .set_cpu_limit(4.0);
+
+        policy.clear_memory_limit();
+        assert_eq!(policy.memory_limit, None);
+        assert_eq!(policy.time_limit, Some(1));
+
+        policy.clear_fork_limit();
+        assert_eq!(policy.fork_limit, None);
+        assert_eq!(policy.time_limit, Some(1));
+
+        policy.clear_cpu_limit();
+        assert_eq!(policy.cpu.limit, None);
+        assert_eq!(policy.time_limit, Some(1));
+
+        policy.clear_time_limit();
+        assert_eq!(policy.time_limit, None);
+    }
+
+    #[test]
+    fn clear_cpu_settings() {
+        let mut policy = CGroupLimitPolicy::default();
+        policy
+            .set_cpu_limit(1.5)
+            .set_cpu_period(Duration::from_millis(10))
+            .set_cpu_weight(50)
+            .set_cpu_burst(Duration::from_millis(5));
+        assert!(policy.cpu.is_set());
+
+        policy
+            .clear_cpu_limit()
+            .clear_cpu_period()
+            .clear_cpu_weight()
+            .clear_cpu_burst();
+        assert!(!policy.cpu.is_set());
+    }
+
+    #[test]
+    fn name_template_needs_uid() {
+        let mut policy = CGroupLimitPolicy::default();
+        policy.set_name_template("judge.{uid}".to_string()).unwrap();
+        assert_eq!(policy.name(42), "judge.42");
+
+        match policy.set_name_template("judge".to_string()) {
+            Err(Error::MalformedNameTemplate(x)) => assert_eq!(x, "judge"),
+            x => panic!("unexpected {:?}", x),
+        }
+        assert_eq!(policy.name(42), "judge.42");
+
+        policy.set_attach_existing(true).unwrap();
+        policy.set_name_template("judge".to_string()).unwrap();
+        assert_eq!(policy.name(42), "judge");
+        assert!(policy.set_attach_existing(false).is_err());
+        assert!(policy.attach_existing);
+    }
+}
@@ -0,0 +1,215 @@
+use {
+    crate::CommonResult,
+    cgroups_rs::{cgroup::Cgroup, memory::MemController, pid::PidController, Controller},
+    nix::{
+        errno::Errno,
+        fcntl::{self, OFlag},
+        poll::{self, PollFd, PollFlags},
+        sys::{
+            eventfd::{self, EfdFlags},
+            inotify::{AddWatchFlags, InitFlags, Inotify},
+            stat::Mode,
+        },
+        unistd,
+    },
+    std::{
+        fs,
+        os::unix::io::{AsRawFd, FromRawFd, OwnedFd},
+        path::{Path, PathBuf},
+        thread::JoinHandle,
+        time::{Duration, Instant},
+    },
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CGroupEvent {
+    OomKill,    // processes were killed by the OOM killer, because of the memory limit
+    ForkFailed, // forks were refused because of the fork limit
+}
+
+// What happened to the cgroup of a container while it was running.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct CGroupEvents {
+    pub oom_kills: u64,
+    pub fork_failures: u64,
+    pub timeline: Vec<(Duration, CGroupEvent)>, // when they were noticed, since the launch
+}
+
+impl CGroupEvents {
+    pub fn memory_limit_exceeded(&self) -> bool {
+        self.oom_kills > 0
+    }
+
+    pub fn fork_limit_exceeded(&self) -> bool {
+        self.fork_failures > 0
+    }
+}
+
+// Reads the counter named `key` from a flat keyed file such as memory.events.
+fn read_counter(path: &Path, key: &str) -> u64 {
+    let content = fs::read_to_string(path).unwrap_or_default();
+    content
+        .lines()
+        .find_map(|line| {
+            let mut fields = line.split_whitespace();
+            match fields.next() {
+                Some(x) if x == key => fields.next()?.parse().ok(),
+                _ => None,
+            }
+        })
+        .unwrap_or(0)
+}
+
+// A counter in a file, counted from where it was when the monitor started, as a cgroup may be
+// reused (see `CGroupLimitPolicy::set_attach_existing()`).
+#[derive(Debug)]
+struct Counter {
+    path: PathBuf,
+    key: &'static str,
+    base: u64,
+    event: CGroupEvent,
+}
+
+impl Counter {
+    fn new(path: PathBuf, key: &'static str, event: CGroupEvent) -> Self {
+        Self {
+            base: read_counter(&path, key),
+            path: path,
+            key: key,
+            event: event,
+        }
+    }
+
+    fn update(&self, events: &mut CGroupEvents, now: Duration) {
+        let value = read_counter(&self.path, self.key).saturating_sub(self.base);
+        let current = match self.event {
+            CGroupEvent::OomKill => &mut events.oom_kills,
+            CGroupEvent::ForkFailed => &mut events.fork_failures,
+        };
+        if value > *current {
+            *current = value;
+            events.timeline.push((now, self.event));
+        }
+    }
+}
+
+// Watches the event counters of a cgroup in the background, woken up by inotify (eventfd for the
+// OOM notification of cgroup v1) whenever they change.
+#[derive(Debug)]
+pub struct CGroupMonitor {
+    cancel: Option<OwnedFd>,
+    handle: Option<JoinHandle<CGroupEvents>>,
+}
+
+impl CGroupMonitor {
+    pub(super) fn spawn(cg: &Cgroup) -> CommonResult<Self> {
+        let inotify = Inotify::init(InitFlags::IN_CLOEXEC | InitFlags::IN_NONBLOCK)?;
+        let inotify_fd = unsafe { OwnedFd::from_raw_fd(inotify.as_raw_fd()) };
+        let mut oom_eventfd = None;
+        let mut counters = Vec::new();
+
+        let memory: Option<&MemController> = cg.controller_of();
+        if let Some(memory) = memory {
+            if cg.v2() {
+                let path = memory.path().join("memory.events");
+                inotify.add_watch(&path, AddWatchFlags::IN_MODIFY)?;
+                counters.push(Counter::new(path, "oom_kill", CGroupEvent::OomKill));
+            } else {
+                let path = memory.path().join("memory.oom_control");
+                let control = unsafe {
+                    OwnedFd::from_raw_fd(fcntl::open(
+                        &path,
+                        OFlag::O_RDONLY | OFlag::O_CLOEXEC,
+                        Mode::empty(),
+                    )?)
+                };
+                let efd = unsafe {
+                    OwnedFd::from_raw_fd(eventfd::eventfd(
+                        0,
+                        EfdFlags::EFD_CLOEXEC | EfdFlags::EFD_NONBLOCK,
+                    )?)
+                };
+                fs::write(
+                    memory.path().join("cgroup.event_control"),
+                    format!("{} {}", efd.as_raw_fd(), control.as_raw_fd()),
+                )?;
+                oom_eventfd = Some(efd);
+                counters.push(Counter::new(path, "oom_kill", CGroupEvent::OomKill));
+            }
+        }
+
+        let pids: Option<&PidController> = cg.controller_of();
+        if let Some(pids) = pids {
+            let path = pids.path().join("pids.events");
+            if path.exists() {
+                inotify.add_watch(&path, AddWatchFlags::IN_MODIFY)?;
+                counters.push(Counter::new(path, "max", CGroupEvent::ForkFailed));
+            }
+        }
+
+        let (cancel_read, cancel_write) = unistd::pipe2(OFlag::O_CLOEXEC)?;
+        let (cancel_read, cancel_write) = unsafe {
+            (
+                OwnedFd::from_raw_fd(cancel_read),
+                OwnedFd::from_raw_fd(cancel_write),
+            )
+        };
+
+        let start = Instant::now();
+        let handle = std::thread::spawn(move || {
+            let mut events = CGroupEvents::default();
+            loop {
+                let mut fds = vec![
+                    PollFd::new(cancel_read.as_raw_fd(), PollFlags::POLLIN),
+                    PollFd::new(inotify_fd.as_raw_fd(), PollFlags::POLLIN),
+                ];
+                if let Some(efd) = &oom_eventfd {
+                    fds.push(PollFd::new(efd.as_raw_fd(), PollFlags::POLLIN));
+                }
+                match poll::poll(&mut fds, -1) {
+                    Ok(_) | Err(nix::Error::Sys(Errno::EINTR)) => {}
+                    Err(_) => break,
+                }
+                // the write end is closed when stopped
+                if fds[0].revents().map_or(false, |x| !x.is_empty()) {
+                    break;
+                }
+
+                let _ = inotify.read_events();
+                if let Some(efd) = &oom_eventfd {
+                    let _ = unistd::read(efd.as_raw_fd(), &mut [0_u8; 8]);
+                }
+                for counter in counters.iter() {
+                    counter.update(&mut events, start.elapsed());
+                }
+            }
+
+            // catch up with what has not been noticed yet
+            for counter in counters.iter() {
+                counter.update(&mut events, start.elapsed());
+            }
+            drop(inotify_fd);
+            events
+        });
+
+        Ok(Self {
+            cancel: Some(cancel_write),
+            handle: Some(handle),
+        })
+    }
+
+    // Stops watching, returns what happened so far.
+    pub fn stop(&mut self) -> CGroupEvents {
+        self.cancel.take();
+        match self.handle.take() {
+            Some(handle) => handle.join().unwrap_or_default(),
+            None => Default::default(),
+        }
+    }
+}
+
+impl Drop for CGroupMonitor {
+    fn drop(&mut self) {
+        self.stop();
+    }
+}
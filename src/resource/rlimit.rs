@@ -0,0 +1,137 @@
+use crate::VoidResult;
+
+// Value meaning "no limit", e.g. for an unlimited stack.
+pub const UNLIMITED: u64 = libc::RLIM_INFINITY;
+
+// POSIX resource limits, set with `setrlimit` right before the target is executed. Each one is
+// both the soft and the hard limit, so that the target cannot raise it again.
+#[derive(Debug, Clone, Default)]
+pub struct RlimitPolicy {
+    stack: Option<u64>,
+    file_size: Option<u64>,
+    open_files: Option<u64>,
+    core: Option<u64>,
+    cpu_time: Option<u64>,
+    address_space: Option<u64>,
+    locked_memory: Option<u64>,
+    message_queue: Option<u64>,
+}
+
+impl RlimitPolicy {
+    // `RLIMIT_STACK`, in bytes.
+    pub fn set_stack_limit(&mut self, value: u64) -> &mut Self {
+        self.stack = Some(value);
+        self
+    }
+
+    // `RLIMIT_FSIZE`, in bytes.
+    pub fn set_file_size_limit(&mut self, value: u64) -> &mut Self {
+        self.file_size = Some(value);
+        self
+    }
+
+    // `RLIMIT_NOFILE`, in number of file descriptors.
+    pub fn set_open_files_limit(&mut self, value: u64) -> &mut Self {
+        self.open_files = Some(value);
+        self
+    }
+
+    // `RLIMIT_CORE`, in bytes. Use 0 to disable core dumps.
+    pub fn set_core_limit(&mut self, value: u64) -> &mut Self {
+        self.core = Some(value);
+        self
+    }
+
+    // `RLIMIT_CPU`, in seconds.
+    pub fn set_cpu_time_limit(&mut self, value: u64) -> &mut Self {
+        self.cpu_time = Some(value);
+        self
+    }
+
+    // `RLIMIT_AS`, in bytes.
+    pub fn set_address_space_limit(&mut self, value: u64) -> &mut Self {
+        self.address_space = Some(value);
+        self
+    }
+
+    // `RLIMIT_MEMLOCK`, in bytes.
+    pub fn set_locked_memory_limit(&mut self, value: u64) -> &mut Self {
+        self.locked_memory = Some(value);
+        self
+    }
+
+    // `RLIMIT_MSGQUEUE`, in bytes.
+    pub fn set_message_queue_limit(&mut self, value: u64) -> &mut Self {
+        self.message_queue = Some(value);
+        self
+    }
+
+    pub fn set_unlimited_stack(&mut self) -> &mut Self {
+        self.set_stack_limit(UNLIMITED)
+    }
+
+    pub fn clear_stack_limit(&mut self) -> &mut Self {
+        self.stack = None;
+        self
+    }
+
+    pub fn clear_file_size_limit(&mut self) -> &mut Self {
+        self.file_size = None;
+        self
+    }
+
+    pub fn clear_open_files_limit(&mut self) -> &mut Self {
+        self.open_files = None;
+        self
+    }
+
+    pub fn clear_core_limit(&mut self) -> &mut Self {
+        self.core = None;
+        self
+    }
+
+    pub fn clear_cpu_time_limit(&mut self) -> &mut Self {
+        self.cpu_time = None;
+        self
+    }
+
+    pub fn clear_address_space_limit(&mut self) -> &mut Self {
+        self.address_space = None;
+        self
+    }
+
+    pub fn clear_locked_memory_limit(&mut self) -> &mut Self {
+        self.locked_memory = None;
+        self
+    }
+
+    pub fn clear_message_queue_limit(&mut self) -> &mut Self {
+        self.message_queue = None;
+        self
+    }
+
+    pub fn apply(&self) -> VoidResult {
+        let limits = [
+            (libc::RLIMIT_STACK, self.stack),
+            (libc::RLIMIT_FSIZE, self.file_size),
+            (libc::RLIMIT_NOFILE, self.open_files),
+            (libc::RLIMIT_CORE, self.core),
+            (libc::RLIMIT_CPU, self.cpu_time),
+            (libc::RLIMIT_AS, self.address_space),
+            (libc::RLIMIT_MEMLOCK, self.locked_memory),
+            (libc::RLIMIT_MSGQUEUE, self.message_queue),
+        ];
+
+        for (resource, value) in limits.iter() {
+            if let Some(value) = value {
+                let limit = libc::rlimit {
+                    rlim_cur: *value as libc::rlim_t,
+                    rlim_max: *value as libc::rlim_t,
+                };
+                nix::errno::Errno::result(unsafe { libc::setrlimit(*resource, &limit) })?;
+            }
+        }
+
+        Ok(())
+    }
+}
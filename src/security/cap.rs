@@ -60,4 +60,8 @@ impl ApplySecurityPolicy for CapabilityPolicy {
         caps::set(None, caps::CapSet::Effective, &ok_caps)?;
         Ok(())
     }
+
+    fn name(&self) -> &str {
+        "capability"
+    }
 }
@@ -3,8 +3,13 @@ use {crate::VoidResult};
 pub mod cap;
 pub mod seccomp;
 
-pub trait ApplySecurityPolicy: std::fmt::Debug {
+pub trait ApplySecurityPolicy: std::fmt::Debug + Send + Sync {
     fn apply(&self) -> VoidResult;
+
+    // Only used to describe errors.
+    fn name(&self) -> &str {
+        "custom"
+    }
 }
 
 pub use cap::CapabilityPolicy;
@@ -4,6 +4,14 @@ use {super::ApplySecurityPolicy, crate::VoidResult};
 pub struct SeccompPolicy {
     pub allow: Vec<String>,
     pub deny: Vec<String>,
+    pub deny_spawn: bool, // denies starting other programs, while threads are still allowed
+}
+
+fn syscall_id(name: &str) -> Option<libc::c_int> {
+    match libscmp::resolve_syscall_name(name) {
+        Some(call_id) if call_id >= 0 => Some(call_id),
+        _ => None,
+    }
 }
 
 fn common_apply(
@@ -17,13 +25,9 @@ fn common_apply(
 
     let mut filter = libscmp::Filter::new(default_action)?;
     for call_name in target.iter() {
-        let call_id = libscmp::resolve_syscall_name(call_name);
-        match call_id {
-            Some(call_id) if call_id >= 0 => {
-                filter.add_rule_exact(matched_action, call_id, &[])?;
-            }
-            _ => {}
-        };
+        if let Some(call_id) = syscall_id(call_name) {
+            filter.add_rule_exact(matched_action, call_id, &[])?;
+        }
     }
 
     filter.load()?;
@@ -35,6 +39,7 @@ impl SeccompPolicy {
         Self {
             allow: Vec::new(),
             deny: Vec::new(),
+            deny_spawn: false,
         }
     }
 
@@ -55,6 +60,35 @@ impl SeccompPolicy {
             Action::Errno(nix::errno::Errno::EACCES as i32),
         )
     }
+
+    // fork() and posix_spawn() of glibc are built on clone() as well, so clone() is only allowed
+    // with CLONE_THREAD. The flags of clone3() are out of reach of the filter, it fails with ENOSYS
+    // instead, on which glibc falls back to clone(). execve() itself can not be denied, as the
+    // target is executed under the filter, but without the others it can only replace itself.
+    fn apply_spawn_filter(&self) -> VoidResult {
+        use libscmp::{Action, Arg};
+        if !self.deny_spawn {
+            return Ok(());
+        }
+
+        let denied = Action::Errno(nix::errno::Errno::EACCES as i32);
+        let mut filter = libscmp::Filter::new(Action::Allow)?;
+        for call_name in ["fork", "vfork", "execveat"].iter() {
+            if let Some(call_id) = syscall_id(call_name) {
+                filter.add_rule_exact(denied, call_id, &[])?;
+            }
+        }
+        if let Some(call_id) = syscall_id("clone") {
+            let without_thread = Arg::new_masked_eq(0, libc::CLONE_THREAD as u64, 0);
+            filter.add_rule_exact(denied, call_id, &[without_thread])?;
+        }
+        if let Some(call_id) = syscall_id("clone3") {
+            let unsupported = Action::Errno(nix::errno::Errno::ENOSYS as i32);
+            filter.add_rule_exact(unsupported, call_id, &[])?;
+        }
+        filter.load()?;
+        Ok(())
+    }
 }
 
 impl Default for SeccompPolicy {
@@ -88,6 +122,7 @@ impl Default for SeccompPolicy {
                 "vm86old".to_string(),
             ],
             allow: Vec::new(),
+            deny_spawn: false,
         }
     }
 }
@@ -95,6 +130,11 @@ impl Default for SeccompPolicy {
 impl ApplySecurityPolicy for SeccompPolicy {
     fn apply(&self) -> VoidResult {
         self.apply_as_whitelist()?;
-        self.apply_as_blacklist()
+        self.apply_as_blacklist()?;
+        self.apply_spawn_filter()
+    }
+
+    fn name(&self) -> &str {
+        "seccomp"
     }
 }